@@ -66,6 +66,12 @@ function App() {
       case wasm.SingleAudioFileType.Ogg:
         audioType = "audio/ogg";
         break;
+      case wasm.SingleAudioFileType.Flac:
+        audioType = "audio/flac";
+        break;
+      case wasm.SingleAudioFileType.Aac:
+        audioType = "audio/mp4";
+        break;
       default:
         assertNever(file.type);
         break;
@@ -127,6 +133,9 @@ function App() {
               {
                 "audio/mpeg": wasm.SingleAudioFileType.Mpeg,
                 "audio/wav": wasm.SingleAudioFileType.Wav,
+                "audio/flac": wasm.SingleAudioFileType.Flac,
+                "audio/mp4": wasm.SingleAudioFileType.Aac,
+                "audio/x-m4a": wasm.SingleAudioFileType.Aac,
               }[file.type] ?? wasm.SingleAudioFileType.Mpeg,
           };
         })
@@ -149,7 +158,7 @@ function App() {
     setFiles(newFiles);
 
     // Initial combine
-    const initialVolumes = new Uint8Array(newFiles.map(() => 100));
+    const initialVolumes = new Float32Array(newFiles.map(() => 1));
     const combinedFile = await audioCombinerRef.current.combine(initialVolumes);
     updateAudioSource(combinedFile);
   };
@@ -163,7 +172,9 @@ function App() {
 
     // 2. Use the persisted merger to get new audio data
     if (audioCombinerRef.current) {
-      const volumes = new Uint8Array(updatedFiles.map((f) => f.volume));
+      const volumes = new Float32Array(
+        updatedFiles.map((f) => f.volume / 100)
+      );
       const combinedFile = await audioCombinerRef.current.combine(volumes);
       updateAudioSource(combinedFile);
     }
@@ -231,7 +242,7 @@ function App() {
       >
         <input
           type="file"
-          accept=".mp3,.wav"
+          accept=".mp3,.wav,.flac,.m4a"
           multiple
           onChange={handleFileChange}
           ref={fileInputRef}
@@ -283,7 +294,7 @@ function App() {
                   <input
                     type="range"
                     min="0"
-                    max="100"
+                    max="200"
                     value={file.volume}
                     onChange={(e) =>
                       updateVolume(file.id, parseInt(e.target.value))
@@ -23,7 +23,7 @@ const api = {
         // Wrap the combiner in a proxy so we can call its methods
         return Comlink.proxy({
             // deno-lint-ignore require-await
-            async combine(volumes: Uint8Array) {
+            async combine(volumes: Float32Array) {
                 const resultFile = combiner.combine(volumes);
                 if (!("bytes" in resultFile)) {
                     throw resultFile;
@@ -0,0 +1,57 @@
+use crate::FileLevels;
+
+/// Peak absolute amplitude and RMS over all interleaved `samples`.
+pub fn levels(samples: &[f32]) -> FileLevels {
+    let mut peak = 0.0f32;
+    let mut sum_of_squares = 0.0f64;
+    for &sample in samples {
+        peak = peak.max(sample.abs());
+        sum_of_squares += sample as f64 * sample as f64;
+    }
+    let rms = if samples.is_empty() {
+        0.0
+    } else {
+        (sum_of_squares / samples.len() as f64).sqrt() as f32
+    };
+    FileLevels { peak, rms }
+}
+
+/// Splits interleaved `samples` into `buckets` equal spans of frames and
+/// returns the minimum and maximum sample of each, across all channels, as
+/// `[min0, max0, min1, max1, ...]`. Empty spans report `0.0` for both.
+pub fn waveform(samples: &[f32], channels: usize, buckets: usize) -> Vec<f32> {
+    let frames = samples.len() / channels;
+    let mut peaks = Vec::with_capacity(buckets * 2);
+    // In u64, since bucket * frames overflows a 32-bit usize for long files
+    let bound = |bucket: usize| (bucket as u64 * frames as u64 / buckets as u64) as usize;
+    for bucket in 0..buckets {
+        let start = bound(bucket);
+        let end = bound(bucket + 1);
+        let span = &samples[start * channels..end * channels];
+        if span.is_empty() {
+            peaks.extend_from_slice(&[0.0, 0.0]);
+            continue;
+        }
+        let (min, max) = span.iter().fold((f32::MAX, f32::MIN), |(min, max), &s| {
+            (min.min(s), max.max(s))
+        });
+        peaks.push(min);
+        peaks.push(max);
+    }
+    peaks
+}
+
+/// The range of frames from the first to the last frame with any channel at
+/// or above `threshold`, as `(start, end)`. Fully quiet `samples` give an
+/// empty range at frame 0.
+pub fn audible_frames(samples: &[f32], channels: usize, threshold: f32) -> (usize, usize) {
+    let audible = |frame: &[f32]| frame.iter().any(|s| s.abs() >= threshold);
+    let frames = samples.chunks_exact(channels);
+    match frames.clone().position(audible) {
+        Some(start) => {
+            let end = samples.len() / channels - frames.rev().position(audible).unwrap_or(0);
+            (start, end)
+        }
+        None => (0, 0),
+    }
+}
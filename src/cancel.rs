@@ -0,0 +1,32 @@
+use js_sys::{Atomics, Int32Array};
+
+use crate::AudioError;
+
+/// A cancel flag passed in from JavaScript: an `Int32Array` whose first
+/// element is set to a nonzero value to request cancellation. Decoding and
+/// mixing run synchronously, so the flag is typically backed by a
+/// `SharedArrayBuffer` and set with `Atomics.store` from another thread.
+/// `AudioCombiner::new_async` yields while decoding, so there the flag can
+/// also be set from the same thread.
+#[derive(Clone, Copy, Default)]
+pub struct Cancel<'a>(Option<&'a Int32Array>);
+
+impl<'a> Cancel<'a> {
+    pub fn new(flag: Option<&'a Int32Array>) -> Self {
+        Self(flag)
+    }
+
+    /// Fails with `AudioError::Cancelled` once cancellation has been requested.
+    pub fn check(&self) -> Result<(), AudioError> {
+        let flag = match self.0 {
+            Some(flag) => flag,
+            None => return Ok(()),
+        };
+        let value = Atomics::load(flag, 0)
+            .map_err(|e| AudioError::Js(format!("Cannot read cancel flag: {:?}", e)))?;
+        if value != 0 {
+            return Err(AudioError::Cancelled);
+        }
+        Ok(())
+    }
+}
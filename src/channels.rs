@@ -0,0 +1,77 @@
+use symphonia::core::audio::Channels;
+
+const MINUS_3DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Left/right weights for each decoded channel, following the ITU-R BS.775
+/// downmix: center and surrounds at -3 dB, LFE dropped.
+pub fn stereo_downmix_weights(channels: Channels) -> Vec<(f32, f32)> {
+    if channels.count() == 1 {
+        return vec![(1.0, 1.0)];
+    }
+
+    let left = Channels::FRONT_LEFT_CENTRE
+        | Channels::FRONT_LEFT_WIDE
+        | Channels::FRONT_LEFT_HIGH
+        | Channels::REAR_LEFT
+        | Channels::REAR_LEFT_CENTRE
+        | Channels::SIDE_LEFT
+        | Channels::TOP_FRONT_LEFT
+        | Channels::TOP_REAR_LEFT;
+    let right = Channels::FRONT_RIGHT_CENTRE
+        | Channels::FRONT_RIGHT_WIDE
+        | Channels::FRONT_RIGHT_HIGH
+        | Channels::REAR_RIGHT
+        | Channels::REAR_RIGHT_CENTRE
+        | Channels::SIDE_RIGHT
+        | Channels::TOP_FRONT_RIGHT
+        | Channels::TOP_REAR_RIGHT;
+
+    channels
+        .iter()
+        .map(|channel| {
+            if channel == Channels::FRONT_LEFT {
+                (1.0, 0.0)
+            } else if channel == Channels::FRONT_RIGHT {
+                (0.0, 1.0)
+            } else if channel.intersects(Channels::LFE1 | Channels::LFE2) {
+                (0.0, 0.0)
+            } else if left.contains(channel) {
+                (MINUS_3DB, 0.0)
+            } else if right.contains(channel) {
+                (0.0, MINUS_3DB)
+            } else {
+                // Center-type channels feed both sides
+                (MINUS_3DB, MINUS_3DB)
+            }
+        })
+        .collect()
+}
+
+/// Spreads interleaved `samples` with `from` channels over `to` channels.
+/// Mono is copied to the first two outputs; otherwise extra outputs are silent.
+pub fn expand_channels(samples: &[f32], from: usize, to: usize) -> Vec<f32> {
+    if from >= to {
+        return samples.to_vec();
+    }
+
+    let mut out = Vec::with_capacity(samples.len() / from * to);
+    for frame in samples.chunks_exact(from) {
+        let start = out.len();
+        out.extend_from_slice(frame);
+        out.resize(start + to, 0.0);
+        if from == 1 {
+            out[start + 1] = frame[0];
+        }
+    }
+    out
+}
+
+/// Reorders interleaved `samples` with `channels` channels so each channel's
+/// samples are contiguous, first channel first.
+pub fn deinterleave(samples: &[f32], channels: usize) -> Vec<f32> {
+    let mut out = Vec::with_capacity(samples.len());
+    for channel in 0..channels {
+        out.extend(samples.iter().skip(channel).step_by(channels));
+    }
+    out
+}
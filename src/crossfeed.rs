@@ -0,0 +1,54 @@
+use std::f64::consts::FRAC_1_SQRT_2;
+
+use crate::filter::Biquad;
+use crate::Crossfeed;
+
+/// Above this the head shadows the far ear, so only lows cross over.
+const CROSSFEED_CUTOFF_HZ: f64 = 700.0;
+
+/// A `Crossfeed` running over interleaved stereo audio.
+pub struct Crossfeeder {
+    amount: f32,
+    /// Past samples of each channel, `delay` frames long.
+    lines: [Vec<f32>; 2],
+    position: usize,
+    filters: [Biquad; 2],
+}
+
+impl Crossfeeder {
+    pub fn new(settings: &Crossfeed, sample_rate: u32) -> Self {
+        let delay = (settings.delay_ms.max(0.0) * sample_rate as f32 / 1000.0).round() as usize;
+        let cutoff = CROSSFEED_CUTOFF_HZ.min(sample_rate as f64 * 0.45);
+        let filter = Biquad::low_pass(sample_rate, cutoff, FRAC_1_SQRT_2);
+        Self {
+            amount: settings.amount.clamp(0.0, 1.0),
+            lines: [vec![0.0; delay], vec![0.0; delay]],
+            position: 0,
+            filters: [filter.clone(), filter],
+        }
+    }
+
+    /// Blends each channel into the other in place, continuing from the
+    /// previous call.
+    pub fn process(&mut self, buffer: &mut [f32]) {
+        // Scaled so a centered, low-pitched signal keeps its level
+        let direct = 1.0 / (1.0 + self.amount);
+        for frame in buffer.chunks_exact_mut(2) {
+            let mut crossed = [0.0; 2];
+            for (channel, crossed) in crossed.iter_mut().enumerate() {
+                let line = &mut self.lines[channel];
+                let delayed = match line.get_mut(self.position) {
+                    Some(past) => std::mem::replace(past, frame[channel]),
+                    None => frame[channel],
+                };
+                *crossed = self.filters[channel].process(delayed as f64) as f32;
+            }
+            if !self.lines[0].is_empty() {
+                self.position = (self.position + 1) % self.lines[0].len();
+            }
+
+            frame[0] = (frame[0] + self.amount * crossed[1]) * direct;
+            frame[1] = (frame[1] + self.amount * crossed[0]) * direct;
+        }
+    }
+}
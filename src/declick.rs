@@ -0,0 +1,66 @@
+use crate::Declick;
+
+/// Per-channel state of the declicker.
+#[derive(Clone, Copy, Default)]
+struct ChannelState {
+    /// Last two input samples, newest first.
+    history: [f32; 2],
+    /// Last output sample.
+    last_output: f32,
+    /// Offset still being added to the input to bridge the last click.
+    offset: f32,
+    /// Samples left until `offset` reaches zero.
+    remaining: usize,
+    /// Whether the previous sample was a click, which throws off the
+    /// prediction for this one.
+    after_click: bool,
+}
+
+/// A `Declick` running over interleaved audio. A click is a sample that
+/// departs from the straight line through the two before it by more than the
+/// threshold. The jump is then bridged by holding the last output level and
+/// fading linearly back to the input over the window.
+pub struct Declicker {
+    threshold: f32,
+    window: usize,
+    states: Vec<ChannelState>,
+}
+
+impl Declicker {
+    pub fn new(settings: &Declick, sample_rate: u32, channels: usize) -> Self {
+        Self {
+            threshold: settings.threshold,
+            window: (settings.window_ms.max(0.0) * sample_rate as f32 / 1000.0).round() as usize,
+            states: vec![ChannelState::default(); channels],
+        }
+    }
+
+    /// Smooths the clicks in `buffer` in place, continuing from the previous
+    /// call.
+    pub fn process(&mut self, buffer: &mut [f32]) {
+        for frame in buffer.chunks_exact_mut(self.states.len()) {
+            for (sample, state) in frame.iter_mut().zip(&mut self.states) {
+                let input = *sample;
+                let predicted = 2.0 * state.history[0] - state.history[1];
+                let click = self.window > 0
+                    && !state.after_click
+                    && (input - predicted).abs() > self.threshold;
+                if click {
+                    state.offset = state.last_output - input;
+                    state.remaining = self.window;
+                }
+                state.after_click = click;
+
+                let mut output = input;
+                if state.remaining > 0 {
+                    output += state.offset * state.remaining as f32 / self.window as f32;
+                    state.remaining -= 1;
+                }
+
+                state.history = [input, state.history[0]];
+                state.last_output = output;
+                *sample = output;
+            }
+        }
+    }
+}
@@ -0,0 +1,455 @@
+use std::convert::TryInto;
+
+use symphonia::core::audio::{AudioBufferRef, SampleBuffer, SignalSpec};
+use symphonia::core::codecs::Decoder;
+use symphonia::core::formats::FormatReader;
+use symphonia::core::meta::MetadataRevision;
+
+use crate::cancel::Cancel;
+use crate::channels;
+use crate::{
+    AudioCombinerSingleFile, AudioError, ChannelMode, FileLevels, MetadataTag, SingleAudioFileType,
+    DEFAULT_SAMPLE_RATE,
+};
+
+/// How many packets are decoded between progress reports.
+pub const PROGRESS_INTERVAL: usize = 64;
+
+/// Decodes `bytes` into interleaved samples laid out according to
+/// `channel_mode`. Without a `file_type` the container is sniffed from the
+/// content alone. `on_progress` is called periodically with the estimated
+/// fraction decoded, and once with `1.0`. `cancel` is checked on the same
+/// schedule.
+pub fn decode_file(
+    bytes: Vec<u8>,
+    file_type: Option<SingleAudioFileType>,
+    channel_mode: ChannelMode,
+    cancel: Cancel,
+    mut on_progress: impl FnMut(f64) -> Result<(), AudioError>,
+) -> Result<AudioCombinerSingleFile, AudioError> {
+    let mut decoder = FileDecoder::new(bytes, file_type, channel_mode)?;
+    while !decoder.decode_packets(PROGRESS_INTERVAL)? {
+        cancel.check()?;
+        if let Some(fraction) = decoder.progress() {
+            on_progress(fraction)?;
+        }
+    }
+    on_progress(1.0)?;
+    Ok(decoder.finish())
+}
+
+/// Maps symphonia errors to `AudioError`s, naming the WAV encoding when
+/// there is one since symphonia's messages don't.
+struct Describe(Option<String>);
+
+impl Describe {
+    fn error(&self, e: symphonia::core::errors::Error) -> AudioError {
+        let message = match &self.0 {
+            Some(encoding) => format!("Cannot decode {} WAV: {}", encoding, e),
+            None => e.to_string(),
+        };
+        match e {
+            symphonia::core::errors::Error::Unsupported(_) => AudioError::Unsupported(message),
+            _ => AudioError::Decode(message),
+        }
+    }
+}
+
+/// A file being decoded a few packets at a time, so callers can report
+/// progress or hand control back to the browser in between.
+pub struct FileDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    describe: Describe,
+    total_frames: Option<u64>,
+    /// Timestamp of the end of the last decoded packet.
+    decoded_to: u64,
+    sample_rate: Option<u32>,
+    source_channels: usize,
+    /// Most frames a packet of this track can hold, if the codec says.
+    max_frames_per_packet: Option<u64>,
+    /// Corrupt packets replaced with silence.
+    skipped_packets: u32,
+    interleaver: Interleaver,
+    metadata: Vec<MetadataTag>,
+}
+
+impl FileDecoder {
+    /// Probes `bytes` and sets up a decoder for its default track.
+    pub fn new(
+        bytes: Vec<u8>,
+        file_type: Option<SingleAudioFileType>,
+        channel_mode: ChannelMode,
+    ) -> Result<Self, AudioError> {
+        let describe = Describe(wav_encoding(&bytes));
+
+        let src = std::io::Cursor::new(bytes);
+        let mss = symphonia::core::io::MediaSourceStream::new(Box::new(src), Default::default());
+
+        let mut hint = symphonia::core::probe::Hint::new();
+        match file_type {
+            Some(SingleAudioFileType::Wav) => {
+                hint.with_extension("wav");
+            }
+            Some(SingleAudioFileType::Mpeg) => {
+                hint.with_extension("mp3");
+            }
+            Some(SingleAudioFileType::Ogg) => {
+                hint.with_extension("ogg");
+            }
+            Some(SingleAudioFileType::Flac) => {
+                hint.with_extension("flac");
+            }
+            Some(SingleAudioFileType::Aac) => {
+                hint.with_extension("m4a");
+            }
+            None => {}
+        }
+
+        let mut probed = symphonia::default::get_probe()
+            .format(&hint, mss, &Default::default(), &Default::default())
+            .map_err(|e| describe.error(e))?;
+
+        // Tags can come before the container (e.g. ID3v2) or from inside it
+        let mut metadata = Vec::new();
+        if let Some(mut probed_metadata) = probed.metadata.get() {
+            push_tags(&mut metadata, probed_metadata.skip_to_latest());
+        }
+        let format = probed.format;
+        let track = format
+            .default_track()
+            .ok_or_else(|| AudioError::Unsupported("No supported audio track".to_string()))?;
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &Default::default())
+            .map_err(|e| describe.error(e))?;
+
+        Ok(Self {
+            total_frames: track.codec_params.n_frames,
+            decoded_to: 0,
+            sample_rate: track.codec_params.sample_rate,
+            source_channels: track.codec_params.channels.map_or(0, |c| c.count()),
+            max_frames_per_packet: track.codec_params.max_frames_per_packet,
+            skipped_packets: 0,
+            interleaver: Interleaver::new(channel_mode),
+            metadata,
+            format,
+            decoder,
+            describe,
+        })
+    }
+
+    /// Decodes up to `packets` more packets. Returns whether the end of the
+    /// file was reached.
+    pub fn decode_packets(&mut self, packets: usize) -> Result<bool, AudioError> {
+        for _ in 0..packets {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(_) => return Ok(true),
+            };
+            self.decoded_to = packet.ts() + packet.dur();
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // A corrupt packet only loses its own samples. They become
+                // silence so the rest of the file stays in time.
+                Err(symphonia::core::errors::Error::DecodeError(_)) => {
+                    let frames = self
+                        .max_frames_per_packet
+                        .map_or(packet.dur(), |max| packet.dur().min(max));
+                    self.interleaver
+                        .push_silence(frames as usize, self.source_channels);
+                    self.skipped_packets += 1;
+                    continue;
+                }
+                Err(e) => return Err(self.describe.error(e)),
+            };
+            self.source_channels = decoded.spec().channels.count();
+            self.sample_rate = Some(decoded.spec().rate);
+            self.interleaver.push(decoded);
+        }
+        Ok(false)
+    }
+
+    /// Estimated fraction of the file decoded so far, if its length is known.
+    pub fn progress(&self) -> Option<f64> {
+        let total = self.total_frames.filter(|&total| total > 0)?;
+        Some((self.decoded_to as f64 / total as f64).min(1.0))
+    }
+
+    /// The decoded samples, together with the tags found along the way.
+    pub fn finish(mut self) -> AudioCombinerSingleFile {
+        push_tags(&mut self.metadata, self.format.metadata().skip_to_latest());
+        AudioCombinerSingleFile {
+            samples: self.interleaver.samples,
+            sample_rate: self.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE),
+            channels: self.interleaver.channels,
+            source_channels: self.source_channels,
+            skipped_packets: self.skipped_packets,
+            levels: FileLevels {
+                peak: 0.0,
+                rms: 0.0,
+            },
+            metadata: self.metadata,
+        }
+    }
+}
+
+/// Replaces NaN samples from malformed files with silence and infinite ones
+/// with full scale, so a single bad sample can't corrupt the whole mix.
+pub fn sanitize(samples: &mut [f32]) {
+    for sample in samples.iter_mut().filter(|s| !s.is_finite()) {
+        *sample = if sample.is_nan() {
+            0.0
+        } else {
+            sample.signum()
+        };
+    }
+}
+
+fn push_tags(metadata: &mut Vec<MetadataTag>, revision: Option<&MetadataRevision>) {
+    for tag in revision.map_or(&[][..], |revision| revision.tags()) {
+        metadata.push(MetadataTag {
+            key: tag
+                .std_key
+                .map_or_else(|| tag.key.clone(), |key| format!("{:?}", key)),
+            // RIFF INFO strings keep their NUL terminator
+            value: tag.value.to_string().trim_end_matches('\0').to_string(),
+        });
+    }
+}
+
+/// File extensions of the formats the compiled-in decoders can read.
+pub fn supported_extensions() -> Vec<String> {
+    use symphonia::core::codecs::{
+        CODEC_TYPE_AAC, CODEC_TYPE_FLAC, CODEC_TYPE_MP3, CODEC_TYPE_PCM_S16LE, CODEC_TYPE_VORBIS,
+    };
+
+    let codecs = symphonia::default::get_codecs();
+    [
+        (CODEC_TYPE_PCM_S16LE, ".wav"),
+        (CODEC_TYPE_MP3, ".mp3"),
+        (CODEC_TYPE_VORBIS, ".ogg"),
+        (CODEC_TYPE_FLAC, ".flac"),
+        (CODEC_TYPE_AAC, ".m4a"),
+        (CODEC_TYPE_AAC, ".aac"),
+    ]
+    .iter()
+    .filter(|(codec, _)| codecs.get_codec(*codec).is_some())
+    .map(|(_, extension)| extension.to_string())
+    .collect()
+}
+
+/// Describes the encoding declared in the `fmt ` chunk of a RIFF WAVE file,
+/// or `None` if `bytes` is not one.
+fn wav_encoding(bytes: &[u8]) -> Option<String> {
+    if bytes.get(0..4)? != b"RIFF" || bytes.get(8..12)? != b"WAVE" {
+        return None;
+    }
+
+    let mut pos = 12;
+    let fmt = loop {
+        let id = bytes.get(pos..pos + 4)?;
+        let size = u32::from_le_bytes(bytes.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+        let body = pos + 8;
+        if id == b"fmt " {
+            break bytes.get(body..body + size.min(bytes.len() - body))?;
+        }
+        // Chunks are padded to an even size
+        pos = body.checked_add(size)?.checked_add(size % 2)?;
+    };
+
+    let mut tag = u16::from_le_bytes(fmt.get(0..2)?.try_into().ok()?);
+    // WAVE_FORMAT_EXTENSIBLE keeps the real tag at the start of its sub-format GUID
+    if tag == 0xfffe {
+        if let Some(sub_format) = fmt.get(24..26) {
+            tag = u16::from_le_bytes([sub_format[0], sub_format[1]]);
+        }
+    }
+    let name = match tag {
+        0x0001 => "PCM",
+        0x0002 => "Microsoft ADPCM",
+        0x0003 => "IEEE float",
+        0x0006 => "A-law",
+        0x0007 => "mu-law",
+        0x0011 => "IMA ADPCM",
+        0x0031 => "GSM 6.10",
+        0x0050 => "MPEG",
+        0x0055 => "MPEG Layer 3",
+        0x00ff | 0x1610 => "AAC",
+        0x0161..=0x0163 => "Windows Media Audio",
+        0x2000 => "AC-3",
+        0xf1ac => "FLAC",
+        _ => "unknown",
+    };
+    Some(format!("{} (format tag 0x{:04x})", name, tag))
+}
+
+/// Gathers decoded packets into one interleaved buffer laid out according to
+/// the channel mode. The signal spec may change between packets, e.g. a
+/// stream switching from mono to stereo.
+struct Interleaver {
+    channel_mode: ChannelMode,
+    samples: Vec<f32>,
+    /// Channels of `samples`.
+    channels: usize,
+    spec: Option<SignalSpec>,
+    sample_buf: Option<SampleBuffer<f32>>,
+    weights: Vec<(f32, f32)>,
+}
+
+impl Interleaver {
+    fn new(channel_mode: ChannelMode) -> Self {
+        Self {
+            channel_mode,
+            samples: Vec::new(),
+            channels: 2,
+            spec: None,
+            sample_buf: None,
+            weights: Vec::new(),
+        }
+    }
+
+    /// Appends `frames` frames of silence in place of a packet that could
+    /// not be decoded, from a source with `source_channels` channels.
+    fn push_silence(&mut self, frames: usize, source_channels: usize) {
+        // Before any audio, silence sets the layout like a first packet would
+        if let ChannelMode::Passthrough = self.channel_mode {
+            if self.spec.is_none() && self.samples.is_empty() && source_channels > 0 {
+                self.channels = source_channels;
+            }
+        }
+        self.samples
+            .resize(self.samples.len() + frames * self.channels, 0.0);
+    }
+
+    fn push(&mut self, decoded: AudioBufferRef) {
+        let spec = *decoded.spec();
+        let num_channels = spec.channels.count();
+
+        // A buffer sized for another layout would misalign the interleaving
+        let fits = self.spec == Some(spec)
+            && self
+                .sample_buf
+                .as_ref()
+                .is_some_and(|buf| buf.capacity() >= decoded.capacity() * num_channels);
+        if !fits {
+            self.sample_buf = None;
+        }
+        if self.spec.map(|s| s.channels) != Some(spec.channels) {
+            self.weights = channels::stereo_downmix_weights(spec.channels);
+        }
+        let first_packet = self.spec.is_none() && self.samples.is_empty();
+        self.spec = Some(spec);
+
+        let buf = self
+            .sample_buf
+            .get_or_insert_with(|| SampleBuffer::<f32>::new(decoded.capacity() as u64, spec));
+        buf.copy_interleaved_ref(decoded);
+        // Before downmixing, which would spread a bad sample to every channel
+        sanitize(buf.samples_mut());
+
+        // Drop a partial trailing frame so channels never shift
+        let samples = buf.samples();
+        let samples = &samples[..samples.len() - samples.len() % num_channels];
+
+        match self.channel_mode {
+            ChannelMode::Stereo => {
+                // Downmix everything to Stereo (2 channels) during ingestion
+                for frame in samples.chunks_exact(num_channels) {
+                    let (mut left, mut right) = (0.0, 0.0);
+                    for (&sample, &(l_weight, r_weight)) in frame.iter().zip(self.weights.iter()) {
+                        left += sample * l_weight;
+                        right += sample * r_weight;
+                    }
+                    self.samples.push(left);
+                    self.samples.push(right);
+                }
+            }
+            ChannelMode::Passthrough => {
+                // Keep the widest layout seen so far, widening earlier samples
+                // when more channels appear
+                if first_packet {
+                    self.channels = num_channels;
+                } else if num_channels > self.channels {
+                    self.samples =
+                        channels::expand_channels(&self.samples, self.channels, num_channels);
+                    self.channels = num_channels;
+                }
+                if num_channels == self.channels {
+                    self.samples.extend_from_slice(samples);
+                } else {
+                    self.samples.extend(channels::expand_channels(
+                        samples,
+                        num_channels,
+                        self.channels,
+                    ));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+    use symphonia::core::audio::{AudioBuffer, Channels, Signal};
+
+    fn packet(channels: &[&[f32]]) -> AudioBuffer<f32> {
+        let layout = match channels.len() {
+            1 => Channels::FRONT_LEFT,
+            _ => Channels::FRONT_LEFT | Channels::FRONT_RIGHT,
+        };
+        let frames = channels[0].len();
+        let mut buf = AudioBuffer::new(frames as u64, SignalSpec::new(44100, layout));
+        buf.render_reserved(Some(frames));
+        for (i, samples) in channels.iter().enumerate() {
+            buf.chan_mut(i).copy_from_slice(samples);
+        }
+        buf
+    }
+
+    fn interleave(channel_mode: ChannelMode, packets: &[AudioBuffer<f32>]) -> (Vec<f32>, usize) {
+        let mut interleaver = Interleaver::new(channel_mode);
+        for p in packets {
+            interleaver.push(AudioBufferRef::F32(Cow::Borrowed(p)));
+        }
+        (interleaver.samples, interleaver.channels)
+    }
+
+    #[test]
+    fn channel_count_changes_between_packets() {
+        let mono = packet(&[&[0.5, 0.5]]);
+        let stereo = packet(&[&[0.1, 0.1, 0.1], &[0.2, 0.2, 0.2]]);
+        let expected = vec![0.5, 0.5, 0.5, 0.5, 0.1, 0.2, 0.1, 0.2, 0.1, 0.2];
+
+        let (samples, channels) =
+            interleave(ChannelMode::Passthrough, &[mono.clone(), stereo.clone()]);
+        assert_eq!(channels, 2);
+        assert_eq!(samples, expected);
+
+        let (samples, channels) = interleave(ChannelMode::Stereo, &[mono.clone(), stereo.clone()]);
+        assert_eq!(channels, 2);
+        assert_eq!(samples, expected);
+
+        let (samples, channels) = interleave(ChannelMode::Passthrough, &[stereo, mono]);
+        assert_eq!(channels, 2);
+        assert_eq!(samples[6..], [0.5, 0.5, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn silence_keeps_later_packets_in_time() {
+        let mono = packet(&[&[0.5, 0.5]]);
+        let stereo = packet(&[&[0.1], &[0.2]]);
+
+        let mut interleaver = Interleaver::new(ChannelMode::Passthrough);
+        interleaver.push_silence(1, 1);
+        interleaver.push(AudioBufferRef::F32(Cow::Borrowed(&mono)));
+        interleaver.push_silence(2, 1);
+        interleaver.push(AudioBufferRef::F32(Cow::Borrowed(&stereo)));
+        assert_eq!(interleaver.channels, 2);
+        assert_eq!(
+            interleaver.samples,
+            [0.0, 0.0, 0.5, 0.5, 0.5, 0.5, 0.0, 0.0, 0.0, 0.0, 0.1, 0.2]
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{ClipMode, Compressor};
+
+/// Number of samples in `buffer` beyond full scale.
+pub fn overs(buffer: &[f32]) -> usize {
+    buffer.iter().filter(|s| s.abs() > 1.0).count()
+}
+
+/// Limits `buffer` to full scale according to `mode`. Returns how many
+/// samples were beyond full scale beforehand.
+pub fn clip(buffer: &mut [f32], mode: ClipMode) -> usize {
+    let overs = overs(buffer);
+    match mode {
+        ClipMode::Hard => {
+            for sample in buffer.iter_mut() {
+                *sample = sample.clamp(-1.0, 1.0);
+            }
+        }
+        ClipMode::Soft => {
+            for sample in buffer.iter_mut() {
+                *sample = soft_clip(*sample);
+            }
+        }
+        ClipMode::None => {}
+    }
+    overs
+}
+
+/// Level below which `ClipMode::Soft` leaves samples untouched.
+const SOFT_CLIP_KNEE: f32 = 0.6;
+
+/// Passes samples below the knee through and bends the part above it
+/// smoothly towards full scale with `tanh`, so only peaks are colored. The
+/// curve has slope 1 at the knee, so there is no kink.
+fn soft_clip(sample: f32) -> f32 {
+    let level = sample.abs();
+    if level <= SOFT_CLIP_KNEE {
+        return sample;
+    }
+    let headroom = 1.0 - SOFT_CLIP_KNEE;
+    let bent = SOFT_CLIP_KNEE + headroom * ((level - SOFT_CLIP_KNEE) / headroom).tanh();
+    bent.copysign(sample)
+}
+
+/// Coefficient of one-pole smoothing that covers ~63% of a step in `ms`.
+fn smoothing(ms: f32, sample_rate: u32) -> f32 {
+    if ms > 0.0 {
+        (-1000.0 / (ms * sample_rate as f32)).exp()
+    } else {
+        0.0
+    }
+}
+
+/// Feed-forward gain computer: follows the level of a signal with separate
+/// attack and release times and reduces gain above a threshold by a ratio.
+pub struct GainComputer {
+    threshold_db: f32,
+    ratio: f32,
+    attack: f32,
+    release: f32,
+    envelope: f32,
+}
+
+impl GainComputer {
+    pub fn new(
+        sample_rate: u32,
+        threshold_db: f32,
+        ratio: f32,
+        attack_ms: f32,
+        release_ms: f32,
+    ) -> Self {
+        Self {
+            threshold_db,
+            ratio,
+            attack: smoothing(attack_ms, sample_rate),
+            release: smoothing(release_ms, sample_rate),
+            envelope: 0.0,
+        }
+    }
+
+    /// Linear gain for the next frame, whose peak amplitude is `level`.
+    pub fn next(&mut self, level: f32) -> f32 {
+        let coefficient = if level > self.envelope {
+            self.attack
+        } else {
+            self.release
+        };
+        self.envelope = coefficient * self.envelope + (1.0 - coefficient) * level;
+
+        let over = 20.0 * self.envelope.max(f32::MIN_POSITIVE).log10() - self.threshold_db;
+        if over <= 0.0 {
+            return 1.0;
+        }
+        let reduction_db = over * (1.0 - 1.0 / self.ratio);
+        10f32.powf(-reduction_db / 20.0)
+    }
+}
+
+/// Noise gate: opens while the level is at or above a threshold, stays open
+/// for a hold time after it drops below, then closes to silence. The gain
+/// glides open and closed over the attack and release times.
+pub struct Gate {
+    threshold: f32,
+    attack: f32,
+    release: f32,
+    hold: usize,
+    /// Frames left before the gate starts closing.
+    holding: usize,
+    gain: f32,
+}
+
+impl Gate {
+    pub fn new(
+        sample_rate: u32,
+        threshold_db: f32,
+        attack_ms: f32,
+        release_ms: f32,
+        hold_ms: f32,
+    ) -> Self {
+        Self {
+            threshold: 10f32.powf(threshold_db / 20.0),
+            attack: smoothing(attack_ms, sample_rate),
+            release: smoothing(release_ms, sample_rate),
+            hold: (hold_ms.max(0.0) * sample_rate as f32 / 1000.0).round() as usize,
+            holding: 0,
+            gain: 0.0,
+        }
+    }
+
+    /// Linear gain for the next frame, whose peak amplitude is `level`.
+    pub fn next(&mut self, level: f32) -> f32 {
+        let open = if level >= self.threshold {
+            self.holding = self.hold;
+            true
+        } else if self.holding > 0 {
+            self.holding -= 1;
+            true
+        } else {
+            false
+        };
+        let (target, coefficient) = if open {
+            (1.0, self.attack)
+        } else {
+            (0.0, self.release)
+        };
+        self.gain = coefficient * self.gain + (1.0 - coefficient) * target;
+        self.gain
+    }
+}
+
+/// A `Compressor` running over interleaved audio. Channels are linked: the
+/// loudest channel of each frame sets the gain for all of them.
+pub struct MasterCompressor {
+    computer: GainComputer,
+    makeup: f32,
+    channels: usize,
+}
+
+impl MasterCompressor {
+    pub fn new(settings: &Compressor, sample_rate: u32, channels: usize) -> Self {
+        Self {
+            computer: GainComputer::new(
+                sample_rate,
+                settings.threshold_db,
+                settings.ratio.max(1.0),
+                settings.attack_ms,
+                settings.release_ms,
+            ),
+            makeup: 10f32.powf(settings.makeup_db / 20.0),
+            channels,
+        }
+    }
+
+    /// Compresses `buffer` in place, continuing from the previous call.
+    pub fn process(&mut self, buffer: &mut [f32]) {
+        for frame in buffer.chunks_exact_mut(self.channels) {
+            let level = frame.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+            let gain = self.computer.next(level) * self.makeup;
+            frame.iter_mut().for_each(|s| *s *= gain);
+        }
+    }
+}
@@ -0,0 +1,49 @@
+/// Linear fade-in/fade-out over a region of `len` frames.
+pub struct Fade {
+    pub len: usize,
+    pub fade_in: usize,
+    pub fade_out: usize,
+}
+
+impl Fade {
+    /// Gain applied to frame `frame` of the region.
+    pub fn gain_at(&self, frame: usize) -> f32 {
+        let mut gain = 1.0;
+        if frame < self.fade_in {
+            gain *= frame as f32 / self.fade_in as f32;
+        }
+        // Mirrors the fade-in, so overlapping fades always sum to unity
+        let remaining = self.len - frame;
+        if remaining <= self.fade_out {
+            gain *= remaining as f32 / self.fade_out as f32;
+        }
+        gain
+    }
+}
+
+/// Gain breakpoints over the mix timeline, linearly interpolated. Before the
+/// first and after the last breakpoint the gain holds steady; without any
+/// breakpoints it is `1.0`.
+#[derive(Default)]
+pub struct Automation {
+    /// `(frame, gain)` pairs sorted by frame.
+    pub points: Vec<(usize, f32)>,
+}
+
+impl Automation {
+    /// Gain at timeline frame `frame`.
+    pub fn gain_at(&self, frame: usize) -> f32 {
+        let next = self.points.partition_point(|&(f, _)| f <= frame);
+        match (
+            next.checked_sub(1).map(|i| self.points[i]),
+            self.points.get(next),
+        ) {
+            (None, None) => 1.0,
+            (Some((_, gain)), None) | (None, Some(&(_, gain))) => gain,
+            (Some((from_frame, from_gain)), Some(&(to_frame, to_gain))) => {
+                let t = (frame - from_frame) as f32 / (to_frame - from_frame) as f32;
+                from_gain + (to_gain - from_gain) * t
+            }
+        }
+    }
+}
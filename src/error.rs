@@ -0,0 +1,71 @@
+use std::fmt;
+
+use wasm_bindgen::JsValue;
+
+/// Why an operation failed. In JS it arrives as an `Error` whose `name` is
+/// the variant name, e.g. `InvalidInput`, so callers can branch on the kind.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AudioError {
+    /// A file could not be read or decoded.
+    Decode(String),
+    /// A file uses a container or encoding this build cannot decode.
+    Unsupported(String),
+    /// An argument or option is out of range or inconsistent with the files.
+    InvalidInput(String),
+    /// The operation would need more memory than its budget allows.
+    TooLarge(String),
+    /// The operation was stopped through its cancel flag.
+    Cancelled,
+    /// A JavaScript callback or value passed in failed.
+    Js(String),
+}
+
+impl AudioError {
+    /// Name of the variant, used as the JS error's `name`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AudioError::Decode(_) => "Decode",
+            AudioError::Unsupported(_) => "Unsupported",
+            AudioError::InvalidInput(_) => "InvalidInput",
+            AudioError::TooLarge(_) => "TooLarge",
+            AudioError::Cancelled => "Cancelled",
+            AudioError::Js(_) => "Js",
+        }
+    }
+
+    /// Prefixes the message with the index of the file it concerns.
+    pub fn in_file(self, index: usize) -> Self {
+        let prefix = |message: String| format!("File {}: {}", index, message);
+        match self {
+            AudioError::Decode(message) => AudioError::Decode(prefix(message)),
+            AudioError::Unsupported(message) => AudioError::Unsupported(prefix(message)),
+            AudioError::InvalidInput(message) => AudioError::InvalidInput(prefix(message)),
+            AudioError::TooLarge(message) => AudioError::TooLarge(prefix(message)),
+            AudioError::Js(message) => AudioError::Js(prefix(message)),
+            AudioError::Cancelled => AudioError::Cancelled,
+        }
+    }
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AudioError::Decode(message)
+            | AudioError::Unsupported(message)
+            | AudioError::InvalidInput(message)
+            | AudioError::TooLarge(message)
+            | AudioError::Js(message) => f.write_str(message),
+            AudioError::Cancelled => f.write_str("Cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for AudioError {}
+
+impl From<AudioError> for JsValue {
+    fn from(error: AudioError) -> Self {
+        let js_error = js_sys::Error::new(&error.to_string());
+        js_error.set_name(error.kind());
+        js_error.into()
+    }
+}
@@ -0,0 +1,172 @@
+use std::f64::consts::{FRAC_1_SQRT_2, PI};
+
+/// Second-order IIR filter in direct form I, with coefficients normalized so
+/// that `a0` is 1.
+#[derive(Clone)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    /// Builds a filter from unnormalized coefficients `b0..b2` and `a0..a2`.
+    pub fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self {
+            b: [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            a: [a[1] / a[0], a[2] / a[0]],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    /// RBJ cookbook low-pass.
+    pub fn low_pass(sample_rate: u32, cutoff: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::angle(sample_rate, cutoff, q);
+        Self::new(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// RBJ cookbook high-pass.
+    pub fn high_pass(sample_rate: u32, cutoff: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::angle(sample_rate, cutoff, q);
+        Self::new(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// RBJ cookbook peaking EQ boosting or cutting `gain_db` around `center`.
+    pub fn peaking(sample_rate: u32, center: f64, q: f64, gain_db: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.0);
+        let (cos, alpha) = Self::angle(sample_rate, center, q);
+        Self::new(
+            [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+            [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+        )
+    }
+
+    /// RBJ cookbook low shelf with a shelf slope of 1.
+    pub fn low_shelf(sample_rate: u32, corner: f64, gain_db: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.0);
+        let (cos, alpha) = Self::angle(sample_rate, corner, FRAC_1_SQRT_2);
+        let sqrt_alpha = 2.0 * a.sqrt() * alpha;
+        Self::new(
+            [
+                a * ((a + 1.0) - (a - 1.0) * cos + sqrt_alpha),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - sqrt_alpha),
+            ],
+            [
+                (a + 1.0) + (a - 1.0) * cos + sqrt_alpha,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - sqrt_alpha,
+            ],
+        )
+    }
+
+    /// RBJ cookbook high shelf with a shelf slope of 1.
+    pub fn high_shelf(sample_rate: u32, corner: f64, gain_db: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.0);
+        let (cos, alpha) = Self::angle(sample_rate, corner, FRAC_1_SQRT_2);
+        let sqrt_alpha = 2.0 * a.sqrt() * alpha;
+        Self::new(
+            [
+                a * ((a + 1.0) + (a - 1.0) * cos + sqrt_alpha),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - sqrt_alpha),
+            ],
+            [
+                (a + 1.0) - (a - 1.0) * cos + sqrt_alpha,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - sqrt_alpha,
+            ],
+        )
+    }
+
+    /// `cos(w0)` and `alpha` of the cookbook formulas.
+    fn angle(sample_rate: u32, frequency: f64, q: f64) -> (f64, f64) {
+        let w0 = 2.0 * PI * frequency / sample_rate as f64;
+        (w0.cos(), w0.sin() / (2.0 * q))
+    }
+
+    pub fn process(&mut self, x0: f64) -> f64 {
+        let y0 = self.b[0] * x0 + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x0, self.x[0]];
+        self.y = [y0, self.y[0]];
+        y0
+    }
+}
+
+/// Runs interleaved `samples` through a cascade of `filters`, keeping
+/// separate filter state for each channel.
+pub fn apply(samples: &mut [f32], channels: usize, filters: &[Biquad]) {
+    for channel in 0..channels {
+        let mut filters = filters.to_vec();
+        for sample in samples.iter_mut().skip(channel).step_by(channels) {
+            *sample = filters
+                .iter_mut()
+                .fold(*sample as f64, |x, filter| filter.process(x)) as f32;
+        }
+    }
+}
+
+/// Corner of the low shelf of `three_band_eq` in Hz.
+const EQ_LOW_HZ: f64 = 250.0;
+/// Center of the mid peak of `three_band_eq` in Hz.
+const EQ_MID_HZ: f64 = 1000.0;
+/// Corner of the high shelf of `three_band_eq` in Hz.
+const EQ_HIGH_HZ: f64 = 4000.0;
+
+/// Low shelf, mid peak and high shelf with the given gains in dB. Bands at
+/// 0 dB are left out, so a neutral EQ is an empty cascade.
+pub fn three_band_eq(sample_rate: u32, low_db: f64, mid_db: f64, high_db: f64) -> Vec<Biquad> {
+    // Keep the corners below Nyquist for low sample rates
+    let limit = sample_rate as f64 * 0.45;
+    let mut filters = Vec::new();
+    if low_db != 0.0 {
+        filters.push(Biquad::low_shelf(sample_rate, EQ_LOW_HZ.min(limit), low_db));
+    }
+    if mid_db != 0.0 {
+        filters.push(Biquad::peaking(
+            sample_rate,
+            EQ_MID_HZ.min(limit),
+            0.7,
+            mid_db,
+        ));
+    }
+    if high_db != 0.0 {
+        filters.push(Biquad::high_shelf(
+            sample_rate,
+            EQ_HIGH_HZ.min(limit),
+            high_db,
+        ));
+    }
+    filters
+}
+
+/// Subtracts the mean of each channel of interleaved `samples` from that
+/// channel, centering it around zero.
+pub fn remove_dc(samples: &mut [f32], channels: usize) {
+    let frames = samples.len() / channels;
+    if frames == 0 {
+        return;
+    }
+    for channel in 0..channels {
+        let sum: f64 = samples
+            .iter()
+            .skip(channel)
+            .step_by(channels)
+            .map(|&s| s as f64)
+            .sum();
+        let mean = (sum / frames as f64) as f32;
+        for sample in samples.iter_mut().skip(channel).step_by(channels) {
+            *sample -= mean;
+        }
+    }
+}
@@ -1,12 +1,33 @@
+mod analysis;
+mod cancel;
+mod channels;
+mod crossfeed;
+mod declick;
+mod decode;
+mod dynamics;
+mod envelope;
+mod error;
+mod filter;
+mod loudness;
+mod mix;
+mod resample;
+mod session;
+mod stretch;
 mod utils;
+mod wav;
 
 use wasm_bindgen::prelude::*;
 
+pub use error::AudioError;
+pub use session::MixSession;
+
 #[wasm_bindgen]
 extern "C" {
     fn alert(s: &str);
     #[wasm_bindgen(js_namespace = Date)]
     fn now() -> f64;
+    #[wasm_bindgen(js_namespace = console, js_name = warn)]
+    fn console_warn(s: &str);
 }
 
 #[wasm_bindgen]
@@ -14,12 +35,103 @@ pub fn greet() {
     alert("Hello, wasm!");
 }
 
+/// File extensions this build can decode, e.g. `[".wav", ".mp3"]`, for use in
+/// an upload dialog's `accept` attribute.
 #[wasm_bindgen]
-#[derive(Clone, Copy)]
+pub fn supported_input_formats() -> Vec<String> {
+    decode::supported_extensions()
+}
+
+/// Reads the header of a WAV file, e.g. one produced by `combine`, to check
+/// what was written.
+#[wasm_bindgen]
+pub fn parse_wav_header(bytes: &[u8]) -> Result<WavHeader, AudioError> {
+    wav::parse_header(bytes)
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum SingleAudioFileType {
     Wav,
     Mpeg,
     Ogg,
+    Flac,
+    /// AAC, in an MP4/M4A container or as a raw ADTS stream.
+    Aac,
+}
+
+impl SingleAudioFileType {
+    /// The canonical MIME type, e.g. `audio/wav`.
+    pub fn mime(self) -> &'static str {
+        match self {
+            SingleAudioFileType::Wav => "audio/wav",
+            SingleAudioFileType::Mpeg => "audio/mpeg",
+            SingleAudioFileType::Ogg => "audio/ogg",
+            SingleAudioFileType::Flac => "audio/flac",
+            SingleAudioFileType::Aac => "audio/mp4",
+        }
+    }
+
+    /// The type for a MIME type such as `audio/mpeg` or `audio/x-wav`,
+    /// ignoring case and parameters like `; codecs=vorbis`.
+    pub fn from_mime(mime: &str) -> Option<SingleAudioFileType> {
+        let essence = mime
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase();
+        match essence.as_str() {
+            "audio/wav" | "audio/x-wav" | "audio/wave" | "audio/vnd.wave" => {
+                Some(SingleAudioFileType::Wav)
+            }
+            "audio/mpeg" | "audio/mp3" | "audio/mpeg3" | "audio/x-mpeg" | "audio/x-mp3" => {
+                Some(SingleAudioFileType::Mpeg)
+            }
+            "audio/ogg" | "audio/vorbis" | "audio/x-vorbis+ogg" | "audio/x-ogg"
+            | "application/ogg" => Some(SingleAudioFileType::Ogg),
+            "audio/flac" | "audio/x-flac" => Some(SingleAudioFileType::Flac),
+            "audio/mp4" | "audio/x-m4a" | "audio/m4a" | "audio/aac" | "audio/aacp"
+            | "audio/x-aac" => Some(SingleAudioFileType::Aac),
+            _ => None,
+        }
+    }
+
+    /// The type given away by the magic number at the start of `bytes`, if
+    /// it is one of the known ones.
+    pub fn sniff(bytes: &[u8]) -> Option<SingleAudioFileType> {
+        match bytes {
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => {
+                Some(SingleAudioFileType::Wav)
+            }
+            [_, _, _, _, b'f', b't', b'y', b'p', ..] => Some(SingleAudioFileType::Aac),
+            [b'I', b'D', b'3', ..] => Some(SingleAudioFileType::Mpeg),
+            // An ADTS header: the MPEG frame sync with the layer bits clear
+            [0xff, second, ..] if second & 0xf6 == 0xf0 => Some(SingleAudioFileType::Aac),
+            // An MPEG audio frame sync: eleven set bits
+            [0xff, second, ..] if second & 0xe0 == 0xe0 => Some(SingleAudioFileType::Mpeg),
+            [b'O', b'g', b'g', b'S', ..] => Some(SingleAudioFileType::Ogg),
+            [b'f', b'L', b'a', b'C', ..] => Some(SingleAudioFileType::Flac),
+            _ => None,
+        }
+    }
+}
+
+/// Maps the MIME type of an upload, e.g. `File.type`, to the matching
+/// `SingleAudioFileType`, or `undefined` for types that can't be decoded.
+#[wasm_bindgen]
+pub fn file_type_from_mime(mime: &str) -> Option<SingleAudioFileType> {
+    SingleAudioFileType::from_mime(mime)
+}
+
+/// How decoded channels are laid out before mixing.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub enum ChannelMode {
+    /// Downmix every file to stereo using ITU-R BS.775 coefficients.
+    Stereo,
+    /// Keep each file's channels; the mix uses the widest file's layout.
+    Passthrough,
 }
 
 #[wasm_bindgen]
@@ -31,148 +143,1905 @@ pub struct SingleAudioFile {
 
 #[wasm_bindgen]
 impl SingleAudioFile {
+    /// Copies `bytes` into wasm memory once. `AudioCombiner::new` then moves
+    /// them into the decoder without copying again. When memory is tight,
+    /// pass the uploads to `AudioCombiner::from_bytes` instead, which copies
+    /// only the file currently being decoded.
+    ///
+    /// Uploads are often mislabeled, so when the bytes start with the magic
+    /// number of a different type, that type is used instead and a warning
+    /// is logged to the console.
     pub fn new(bytes: Vec<u8>, r#type: SingleAudioFileType) -> Self {
+        let r#type = match SingleAudioFileType::sniff(&bytes) {
+            Some(detected) if detected != r#type => {
+                warn(&format!(
+                    "File labeled {:?} looks like {:?}, decoding it as {:?}",
+                    r#type, detected, detected
+                ));
+                detected
+            }
+            _ => r#type,
+        };
         Self { bytes, r#type }
     }
+
+    /// Decodes the file and re-encodes it as a mono 16-bit WAV at its own
+    /// sample rate, averaging the left and right channels. Files with more
+    /// channels are downmixed to stereo first, like `ChannelMode::Stereo`.
+    pub fn to_mono(&self) -> Result<SingleAudioFile, AudioError> {
+        self.convert(1)
+    }
+
+    /// Decodes the file and re-encodes it as a stereo 16-bit WAV at its own
+    /// sample rate. Mono becomes the same signal on both sides; files with
+    /// more channels are downmixed like `ChannelMode::Stereo`.
+    pub fn to_stereo(&self) -> Result<SingleAudioFile, AudioError> {
+        self.convert(2)
+    }
+}
+
+impl SingleAudioFile {
+    fn convert(&self, channels: usize) -> Result<SingleAudioFile, AudioError> {
+        let decoded = decode::decode_file(
+            self.bytes.clone(),
+            Some(self.r#type),
+            ChannelMode::Stereo,
+            Default::default(),
+            |_| Ok(()),
+        )?;
+        let samples = if channels == 1 {
+            decoded
+                .samples
+                .chunks_exact(2)
+                .map(|frame| (frame[0] + frame[1]) * 0.5)
+                .collect()
+        } else {
+            decoded.samples
+        };
+        let bytes = wav::create_wav_container(
+            &samples,
+            decoded.sample_rate,
+            channels as u16,
+            SampleFormat::Pcm16,
+            Dither::None,
+        );
+        Ok(SingleAudioFile::new(bytes, SingleAudioFileType::Wav))
+    }
+}
+
+/// A mixed WAV file together with the properties of its audio, so callers
+/// don't have to parse the header to display them.
+#[wasm_bindgen]
+pub struct CombinedAudioFile {
+    #[wasm_bindgen(getter_with_clone)]
+    pub bytes: Vec<u8>,
+    pub r#type: SingleAudioFileType,
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub duration_seconds: f64,
+    /// How many samples went beyond full scale before clipping. Anything
+    /// above zero means the levels should come down.
+    pub clipped_samples: u32,
+}
+
+#[wasm_bindgen]
+impl CombinedAudioFile {
+    /// The file as a `data:` URL, e.g. for an `<audio>` element's `src`
+    /// without creating a `Blob`. Base64 makes it a third larger than
+    /// `bytes`, so prefer a `Blob` URL for long mixes.
+    pub fn to_data_url(&self) -> String {
+        format!(
+            "data:{};base64,{}",
+            self.r#type.mime(),
+            utils::base64(&self.bytes)
+        )
+    }
+}
+
+impl CombinedAudioFile {
+    fn wav(
+        bytes: Vec<u8>,
+        sample_rate: u32,
+        channels: usize,
+        frames: usize,
+        clipped_samples: usize,
+    ) -> Self {
+        Self {
+            bytes,
+            r#type: SingleAudioFileType::Wav,
+            sample_rate,
+            channels: channels as u32,
+            duration_seconds: frames as f64 / sample_rate as f64,
+            clipped_samples: clipped_samples as u32,
+        }
+    }
+}
+
+/// The `fmt ` chunk fields and `data` size of a WAV file, see
+/// `parse_wav_header`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WavHeader {
+    /// `1` for integer PCM, `3` for IEEE float, `0xFFFE` for the extensible
+    /// header written for more than two channels.
+    pub format_tag: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// Bytes per second of audio.
+    pub byte_rate: u32,
+    /// Bytes per frame.
+    pub block_align: u16,
+    pub bits_per_sample: u16,
+    /// Size in bytes of the sample data.
+    pub data_size: u32,
+}
+
+/// Equal-power pan law, scaled so a centered file keeps unity gain.
+fn pan_gains(pan: f32) -> (f32, f32) {
+    // Exact, so unpanned files pass through bit for bit
+    if pan == 0.0 {
+        return (1.0, 1.0);
+    }
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+    (
+        angle.cos() * std::f32::consts::SQRT_2,
+        angle.sin() * std::f32::consts::SQRT_2,
+    )
+}
+
+/// Linear gain of `db` decibels; negative infinity gives silence.
+fn db_to_linear(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+fn normalize_peak(buffer: &mut [f32]) {
+    let peak = buffer.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+    if peak > 1.0 {
+        for sample in buffer.iter_mut() {
+            *sample /= peak;
+        }
+    }
+}
+
+/// How long the mixed output is.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Default)]
+pub enum LengthPolicy {
+    /// Run until the last file ends.
+    #[default]
+    Longest,
+    /// Stop when the first file ends.
+    Shortest,
+    /// Use `MixOptions::fixed_length_ms`, truncating or padding with silence.
+    Fixed,
+}
+
+/// Where shorter files sit within the length of the longest one.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Default)]
+pub enum Alignment {
+    /// Start every file together, leaving silence after shorter ones.
+    #[default]
+    Start,
+    /// End every file together, leaving silence before shorter ones.
+    End,
+}
+
+/// How samples beyond full scale are handled after mixing.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Default)]
+pub enum ClipMode {
+    /// Clamp to `[-1.0, 1.0]`.
+    #[default]
+    Hard,
+    /// Saturate peaks smoothly with `tanh`. Samples below 0.6 pass through
+    /// unchanged, so the level of the rest of the mix is kept.
+    Soft,
+    /// Leave samples untouched. `combine_raw` can then return values beyond
+    /// full scale; WAV output still clamps when converting to integers.
+    None,
+}
+
+/// Sample encoding of WAV output.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Default)]
+pub enum SampleFormat {
+    /// 8-bit unsigned PCM centered on 128, for legacy players.
+    Pcm8,
+    #[default]
+    Pcm16,
+    Pcm24,
+    /// 32-bit IEEE float. Keeps the mix unquantized, including values beyond
+    /// full scale when clipping is off.
+    Float32,
+}
+
+/// Noise added when converting the float mix to integer samples. Has no
+/// effect on `Float32` output.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Default)]
+pub enum Dither {
+    /// Plain truncation, as before dithering was added.
+    #[default]
+    None,
+    /// Triangular (TPDF) dither of +-1 LSB, which decorrelates quantization
+    /// error from the signal on quiet passages.
+    Tpdf,
+    /// TPDF dither with first-order noise shaping.
+    NoiseShaped,
+}
+
+/// Order of the samples returned by `combine_raw_with`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Default)]
+pub enum SampleLayout {
+    /// Frame by frame: `L0, R0, L1, R1, ...`, as in a WAV file.
+    #[default]
+    Interleaved,
+    /// Channel by channel: every sample of the first channel, then every
+    /// sample of the next, e.g. for `AudioBuffer.copyToChannel` with
+    /// `subarray`s of the result.
+    Planar,
+}
+
+/// Response of a per-file filter, see `AudioCombiner::apply_filter`.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub enum FilterType {
+    LowPass,
+    HighPass,
+}
+
+/// Settings of the master bus compressor.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct Compressor {
+    /// Level in dBFS above which gain is reduced.
+    pub threshold_db: f32,
+    /// How many dB of input above the threshold produce 1 dB of output.
+    pub ratio: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+    /// Gain in dB applied after compression.
+    pub makeup_db: f32,
+}
+
+#[wasm_bindgen]
+impl Compressor {
+    pub fn new(
+        threshold_db: f32,
+        ratio: f32,
+        attack_ms: f32,
+        release_ms: f32,
+        makeup_db: f32,
+    ) -> Self {
+        Self {
+            threshold_db,
+            ratio,
+            attack_ms,
+            release_ms,
+            makeup_db,
+        }
+    }
+
+    /// A limiter holding peaks near `threshold_db`: infinite ratio, instant
+    /// attack and a 50 ms release.
+    pub fn limiter(threshold_db: f32) -> Self {
+        Self::new(threshold_db, f32::INFINITY, 0.0, 50.0, 0.0)
+    }
+}
+
+/// Settings of the declicker, which smooths sudden jumps such as those left
+/// where a trim cuts into a loud passage.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct Declick {
+    /// How far, in full-scale units, a sample may depart from the line
+    /// through the two before it before it counts as a click. Lower values
+    /// catch smaller clicks but may also soften loud high frequencies.
+    pub threshold: f32,
+    /// Length of the fade that bridges a click.
+    pub window_ms: f32,
+}
+
+#[wasm_bindgen]
+impl Declick {
+    pub fn new(threshold: f32, window_ms: f32) -> Self {
+        Self {
+            threshold,
+            window_ms,
+        }
+    }
+}
+
+/// Settings of the headphone crossfeed, which blends a delayed, low-passed
+/// copy of each channel into the other so hard-panned mixes sound closer to
+/// speakers.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct Crossfeed {
+    /// Level of the blended signal relative to the direct one, from `0.0`
+    /// (none) to `1.0`. Around `0.3` is a common starting point.
+    pub amount: f32,
+    /// How much later the blended signal arrives, around `0.3` ms like the
+    /// path from one ear to the other.
+    pub delay_ms: f32,
+}
+
+#[wasm_bindgen]
+impl Crossfeed {
+    pub fn new(amount: f32, delay_ms: f32) -> Self {
+        Self { amount, delay_ms }
+    }
+}
+
+/// Settings for a single `combine_with` call.
+#[wasm_bindgen]
+#[derive(Clone, Default)]
+pub struct MixOptions {
+    /// Linear gain per file, parallel to the combiner's files. Must have
+    /// exactly one entry per file.
+    #[wasm_bindgen(getter_with_clone)]
+    pub volumes: Vec<f32>,
+    /// Start time of each file in milliseconds, parallel to `volumes`.
+    /// Missing entries start at 0.
+    #[wasm_bindgen(getter_with_clone)]
+    pub offsets_ms: Vec<f64>,
+    /// Stereo position of each file from `-1.0` (left) to `1.0` (right),
+    /// parallel to `volumes`. Missing entries stay centered.
+    #[wasm_bindgen(getter_with_clone)]
+    pub pans: Vec<f32>,
+    /// Stereo width of each file, parallel to `volumes`: `1.0` is unchanged,
+    /// `0.0` collapses the file to mono and values above `1.0` widen it by
+    /// boosting the side signal. Missing entries stay at `1.0`.
+    #[wasm_bindgen(getter_with_clone)]
+    pub widths: Vec<f32>,
+    /// Where each file's used region starts, in milliseconds into the file.
+    /// Missing entries start at the beginning.
+    #[wasm_bindgen(getter_with_clone)]
+    pub trim_starts_ms: Vec<f64>,
+    /// Where each file's used region ends, in milliseconds into the file.
+    /// Missing entries run to the end; values past the end are clamped.
+    #[wasm_bindgen(getter_with_clone)]
+    pub trim_ends_ms: Vec<f64>,
+    /// Linear fade-in length of each file in milliseconds. `0` means no fade.
+    #[wasm_bindgen(getter_with_clone)]
+    pub fade_ins_ms: Vec<f64>,
+    /// Linear fade-out length of each file in milliseconds. `0` means no fade.
+    #[wasm_bindgen(getter_with_clone)]
+    pub fade_outs_ms: Vec<f64>,
+    /// Scale the mix down so it never exceeds full scale.
+    pub normalize: bool,
+    /// Channel count of the output. `1` averages all channels to mono;
+    /// unset keeps the combiner's layout.
+    pub output_channels: Option<u32>,
+    /// When set, `combine_with` mixes and encodes this many frames at a time
+    /// instead of holding the whole mix as floats, bounding peak memory.
+    pub window_frames: Option<u32>,
+    /// Upper bound in bytes on the memory a mix may take for its float
+    /// buffers and output, counting only what the call actually allocates:
+    /// one window of floats when `combine_with` runs windowed, or just the
+    /// rendered part for `combine_range`. A mix estimated to need more fails
+    /// up front with a `TooLarge` error instead of running wasm out of
+    /// memory. Unset means no limit.
+    pub memory_budget_bytes: Option<u32>,
+    pub length_policy: LengthPolicy,
+    /// Whether files start or end together. `offsets_ms` still apply on top.
+    pub alignment: Alignment,
+    /// Output length in milliseconds when `length_policy` is `Fixed`.
+    pub fixed_length_ms: f64,
+    /// Linear gain on the whole mix, applied to the summed files before the
+    /// master effects and normalizing. Unset means `1.0`.
+    pub master_gain: Option<f32>,
+    /// Compressor on the mixed output, applied before normalizing and
+    /// clipping. Unset leaves the dynamics alone.
+    pub compressor: Option<Compressor>,
+    /// Smooths clicks in the mix before the compressor. Unset leaves the mix
+    /// alone.
+    pub declick: Option<Declick>,
+    /// Crossfeed for headphone listening, applied after the declicker. Needs
+    /// stereo output. Unset leaves the stereo image alone.
+    pub crossfeed: Option<Crossfeed>,
+    pub clip_mode: ClipMode,
+    pub sample_format: SampleFormat,
+    pub dither: Dither,
+    /// Layout of `combine_raw_with` output. WAV output is always interleaved.
+    pub layout: SampleLayout,
+    /// Sum the files in 64-bit floats, which keeps quiet passages clean when
+    /// mixing dozens of files at the cost of some speed.
+    pub high_precision: bool,
+    /// When the first element of this array becomes nonzero, the mix stops
+    /// with the error `"Cancelled"`. See `AudioCombiner::new`.
+    #[wasm_bindgen(getter_with_clone)]
+    pub cancel_flag: Option<js_sys::Int32Array>,
+    looping: Vec<bool>,
+    muted: Vec<bool>,
+    inverted: Vec<bool>,
+    automation: Vec<Vec<(f64, f32)>>,
+    markers: Vec<(f64, String)>,
+}
+
+#[wasm_bindgen]
+impl MixOptions {
+    pub fn new(volumes: Vec<f32>) -> Self {
+        Self {
+            volumes,
+            ..Default::default()
+        }
+    }
+
+    /// Makes file `index` repeat seamlessly until the end of the mix. A
+    /// looping file counts once toward the mix length, and only its final
+    /// repetition fades out.
+    pub fn set_looping(&mut self, index: usize, looping: bool) {
+        if self.looping.len() <= index {
+            self.looping.resize(index + 1, false);
+        }
+        self.looping[index] = looping;
+    }
+
+    /// Leaves file `index` out of the mix entirely, without the cost of
+    /// mixing it at zero volume. A muted file still counts toward the mix
+    /// length, so muting never shortens the output.
+    pub fn set_muted(&mut self, index: usize, muted: bool) {
+        if self.muted.len() <= index {
+            self.muted.resize(index + 1, false);
+        }
+        self.muted[index] = muted;
+    }
+
+    /// Flips the polarity of file `index`, e.g. to fix phase cancellation
+    /// between two microphones on the same source. The volume stays positive.
+    pub fn set_inverted(&mut self, index: usize, inverted: bool) {
+        if self.inverted.len() <= index {
+            self.inverted.resize(index + 1, false);
+        }
+        self.inverted[index] = inverted;
+    }
+
+    /// Adds a marker named `label` at `time_ms` on the mix timeline, e.g. a
+    /// chapter start. Markers are written to WAV output as cue points, which
+    /// many players and editors show.
+    pub fn add_marker(&mut self, time_ms: f64, label: String) {
+        self.markers.push((time_ms, label));
+    }
+
+    pub fn clear_markers(&mut self) {
+        self.markers.clear();
+    }
+
+    /// Automates the gain of file `index` over time with breakpoints at
+    /// `times_ms` on the mix timeline, linearly interpolating between the
+    /// matching `gains`. The gain holds at the first and last breakpoint
+    /// outside that range and multiplies with the file's volume. Empty lists
+    /// remove the automation.
+    pub fn set_automation(
+        &mut self,
+        index: usize,
+        times_ms: Vec<f64>,
+        gains: Vec<f32>,
+    ) -> Result<(), AudioError> {
+        if times_ms.len() != gains.len() {
+            return Err(AudioError::InvalidInput(format!(
+                "Got {} breakpoint times but {} gains",
+                times_ms.len(),
+                gains.len()
+            )));
+        }
+        if times_ms.iter().any(|t| t.is_nan()) || times_ms.windows(2).any(|pair| pair[0] > pair[1])
+        {
+            return Err(AudioError::InvalidInput(
+                "Breakpoint times must be in increasing order".to_string(),
+            ));
+        }
+        if self.automation.len() <= index {
+            self.automation.resize(index + 1, Vec::new());
+        }
+        self.automation[index] = times_ms.into_iter().zip(gains).collect();
+        Ok(())
+    }
+}
+
+/// Loudness of a decoded file, as linear amplitudes.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Default)]
+pub struct FileLevels {
+    pub peak: f32,
+    pub rms: f32,
+}
+
+/// What was decoded for one file.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct FileInfo {
+    /// Samples per channel after resampling to the combiner's rate.
+    pub sample_count: u32,
+    pub duration_seconds: f64,
+    /// Sample rate of the source before resampling.
+    pub sample_rate: u32,
+    /// Channel count of the source before any downmix.
+    pub channels: u32,
+    /// Corrupt packets that could not be decoded and were replaced with
+    /// silence of the same length. Nonzero means the upload is damaged.
+    pub skipped_packets: u32,
+}
+
+/// One tag read from a file, such as its title or artist.
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct MetadataTag {
+    /// Symphonia's standard name for the tag when it has one (e.g.
+    /// `TrackTitle`, `Artist`), otherwise the key as stored in the file.
+    #[wasm_bindgen(getter_with_clone)]
+    pub key: String,
+    #[wasm_bindgen(getter_with_clone)]
+    pub value: String,
 }
 
-fn create_wav_container(samples: &[f32], sample_rate: u32) -> Vec<u8> {
-    let mut wav = Vec::new();
-    let data_size = (samples.len() * 2) as u32; // 2 bytes per sample (i16)
+const DEFAULT_SAMPLE_RATE: u32 = 44100;
 
-    // RIFF Header
-    wav.extend_from_slice(b"RIFF");
-    wav.extend_from_slice(&(36 + data_size).to_le_bytes());
-    wav.extend_from_slice(b"WAVE");
+/// Four octaves either way. Beyond that the stretch before resampling grows
+/// the file by orders of magnitude.
+const MAX_PITCH_SHIFT_SEMITONES: f64 = 48.0;
 
-    // fmt chunk
-    wav.extend_from_slice(b"fmt ");
-    wav.extend_from_slice(&16u32.to_le_bytes());
-    wav.extend_from_slice(&1u16.to_le_bytes());
-    wav.extend_from_slice(&2u16.to_le_bytes()); // Hardcoded Stereo
-    wav.extend_from_slice(&sample_rate.to_le_bytes());
-    wav.extend_from_slice(&(sample_rate * 4).to_le_bytes());
-    wav.extend_from_slice(&4u16.to_le_bytes());
-    wav.extend_from_slice(&16u16.to_le_bytes());
+/// Largest boost or cut `apply_eq` accepts per band. The edit can't be
+/// undone, so NaN or runaway gains are rejected rather than applied.
+const MAX_EQ_GAIN_DB: f64 = 24.0;
 
-    // data chunk
-    wav.extend_from_slice(b"data");
-    wav.extend_from_slice(&data_size.to_le_bytes());
+/// Speed factors `time_stretch` and `change_speed` accept, from four times
+/// slower to four times faster. Slower still would grow the file without
+/// bound.
+const SPEED_FACTORS: std::ops::RangeInclusive<f64> = 0.25..=4.0;
 
-    for &sample in samples {
-        let clamped = sample.clamp(-1.0, 1.0);
-        let s = (clamped * i16::MAX as f32) as i16;
-        wav.extend_from_slice(&s.to_le_bytes());
+/// Checks the arguments shared by the decoding constructors and installs the
+/// panic hook.
+fn check_sources(target_sample_rate: Option<u32>, count: usize) -> Result<(), AudioError> {
+    if target_sample_rate == Some(0) {
+        return Err(AudioError::InvalidInput(
+            "Target sample rate must be greater than 0".to_string(),
+        ));
+    }
+    if count == 0 {
+        return Err(AudioError::InvalidInput(
+            "No files were provided, at least one is needed".to_string(),
+        ));
+    }
+
+    utils::set_panic_hook();
+    Ok(())
+}
+
+/// Logs `message` with `console.warn`. Native builds, i.e. tests, have no
+/// console to log to.
+fn warn(message: &str) {
+    #[cfg(target_arch = "wasm32")]
+    console_warn(message);
+    #[cfg(not(target_arch = "wasm32"))]
+    let _ = message;
+}
+
+/// Calls `on_progress`, if given, as `(fileIndex, fraction)`.
+fn report_progress(
+    on_progress: &Option<js_sys::Function>,
+    index: usize,
+    fraction: f64,
+) -> Result<(), AudioError> {
+    match on_progress {
+        Some(callback) => callback
+            .call2(
+                &JsValue::NULL,
+                &JsValue::from(index as u32),
+                &JsValue::from(fraction),
+            )
+            .map(|_| ())
+            .map_err(|e| AudioError::Js(format!("Progress callback failed: {:?}", e))),
+        None => Ok(()),
     }
-    wav
 }
 
 struct AudioCombinerSingleFile {
     samples: Vec<f32>,
+    sample_rate: u32,
+    channels: usize,
+    source_channels: usize,
+    skipped_packets: u32,
+    levels: FileLevels,
+    metadata: Vec<MetadataTag>,
 }
 #[wasm_bindgen]
 pub struct AudioCombiner {
     files: Vec<AudioCombinerSingleFile>,
+    sample_rate: u32,
+    channels: usize,
+    channel_mode: ChannelMode,
 }
 
 #[wasm_bindgen]
 impl AudioCombiner {
-    pub fn new(files: Vec<SingleAudioFile>) -> Result<AudioCombiner, String> {
-        let mut processed_files = Vec::with_capacity(files.len());
+    /// Decodes `files` and resamples them to a common rate. The rate defaults to
+    /// the highest rate among the files unless `target_sample_rate` is given.
+    ///
+    /// `on_progress`, if given, is called as `(fileIndex, fraction)` while
+    /// each file decodes. `channel_mode` defaults to `ChannelMode::Stereo`.
+    ///
+    /// Decoding stops with the error `"Cancelled"` once the first element of
+    /// `cancel_flag` becomes nonzero. Back it with a `SharedArrayBuffer` to set
+    /// it from another thread while this call runs.
+    pub fn new(
+        files: Vec<SingleAudioFile>,
+        target_sample_rate: Option<u32>,
+        on_progress: Option<js_sys::Function>,
+        channel_mode: Option<ChannelMode>,
+        cancel_flag: Option<js_sys::Int32Array>,
+    ) -> Result<AudioCombiner, AudioError> {
+        let sources = files
+            .into_iter()
+            .map(|file| (file.bytes, Some(file.r#type)));
+        Self::from_sources(
+            sources,
+            target_sample_rate,
+            on_progress,
+            channel_mode,
+            cancel_flag,
+        )
+    }
+
+    /// Like `new`, but detects each file's format from its content instead of
+    /// requiring a `SingleAudioFileType`. Each file is copied out of its
+    /// `Uint8Array` right before it decodes and released right after, so wasm
+    /// memory never holds more than one file's encoded bytes.
+    pub fn from_bytes(
+        files: Vec<js_sys::Uint8Array>,
+        target_sample_rate: Option<u32>,
+        on_progress: Option<js_sys::Function>,
+        channel_mode: Option<ChannelMode>,
+        cancel_flag: Option<js_sys::Int32Array>,
+    ) -> Result<AudioCombiner, AudioError> {
+        let sources = files.iter().map(|bytes| (bytes.to_vec(), None));
+        Self::from_sources(
+            sources,
+            target_sample_rate,
+            on_progress,
+            channel_mode,
+            cancel_flag,
+        )
+    }
 
-        for file in files {
-            let mut decoded_samples = Vec::new();
-            let src = std::io::Cursor::new(file.bytes);
-            let mss =
-                symphonia::core::io::MediaSourceStream::new(Box::new(src), Default::default());
+    /// Like `new`, but returns a `Promise` and hands control back to the
+    /// browser every few packets while decoding, so the page can repaint and
+    /// handle input instead of freezing until every file is decoded.
+    pub async fn new_async(
+        files: Vec<SingleAudioFile>,
+        target_sample_rate: Option<u32>,
+        on_progress: Option<js_sys::Function>,
+        channel_mode: Option<ChannelMode>,
+        cancel_flag: Option<js_sys::Int32Array>,
+    ) -> Result<AudioCombiner, AudioError> {
+        let channel_mode = channel_mode.unwrap_or(ChannelMode::Stereo);
+        let cancel = cancel::Cancel::new(cancel_flag.as_ref());
+        check_sources(target_sample_rate, files.len())?;
 
-            let mut hint = symphonia::core::probe::Hint::new();
-            match file.r#type {
-                SingleAudioFileType::Wav => {
-                    hint.with_extension("wav");
-                }
-                SingleAudioFileType::Mpeg => {
-                    hint.with_extension("mp3");
-                }
-                SingleAudioFileType::Ogg => {
-                    hint.with_extension("ogg");
+        let mut processed_files = Vec::with_capacity(files.len());
+        for (i, file) in files.into_iter().enumerate() {
+            let mut decoder = decode::FileDecoder::new(file.bytes, Some(file.r#type), channel_mode)
+                .map_err(|e| e.in_file(i))?;
+            while !decoder
+                .decode_packets(decode::PROGRESS_INTERVAL)
+                .map_err(|e| e.in_file(i))?
+            {
+                cancel.check()?;
+                if let Some(fraction) = decoder.progress() {
+                    report_progress(&on_progress, i, fraction)?;
                 }
+                utils::yield_to_event_loop().await.map_err(|e| {
+                    AudioError::Js(format!("Cannot yield to the event loop: {:?}", e))
+                })?;
             }
+            report_progress(&on_progress, i, 1.0)?;
+            processed_files.push(decoder.finish());
+        }
+
+        Self::assemble(processed_files, target_sample_rate, channel_mode, cancel)
+    }
+
+    /// Builds a combiner from audio that is already decoded, e.g. from
+    /// `AudioBuffer`s of the Web Audio API, skipping decoding entirely. Each
+    /// buffer holds interleaved samples with `channels` channels at
+    /// `sample_rate`, which become the layout and rate of the mix. Files added
+    /// later keep their own channel layout, like `ChannelMode::Passthrough`.
+    pub fn from_samples(
+        buffers: Vec<js_sys::Float32Array>,
+        sample_rate: u32,
+        channels: u32,
+    ) -> Result<AudioCombiner, AudioError> {
+        Self::from_decoded(
+            buffers.iter().map(|buffer| buffer.to_vec()),
+            sample_rate,
+            channels as usize,
+        )
+    }
+
+    /// Decodes `file` and appends it without touching the files already
+    /// decoded. It becomes the last entry of `volumes` and every other per-file
+    /// setting. The mix keeps its sample rate and channel layout, so the file
+    /// cannot have more channels than the mix.
+    ///
+    /// Mixing always reads the resident decoded samples, so layering takes
+    /// one at a time costs a single decode per take: keep one combiner alive
+    /// and call `add_file` for each new take instead of building a new one.
+    pub fn add_file(&mut self, file: SingleAudioFile) -> Result<(), AudioError> {
+        let mut decoded = decode::decode_file(
+            file.bytes,
+            Some(file.r#type),
+            self.channel_mode,
+            Default::default(),
+            |_| Ok(()),
+        )?;
+        if decoded.channels > self.channels {
+            return Err(AudioError::InvalidInput(format!(
+                "Cannot add a {} channel file to a {} channel mix",
+                decoded.channels, self.channels
+            )));
+        }
+        self.conform(&mut decoded);
+        self.files.push(decoded);
+        Ok(())
+    }
+
+    /// Removes file `index`; later files move down by one index.
+    pub fn remove_file(&mut self, index: usize) -> Result<(), AudioError> {
+        self.file(index)?;
+        if self.files.len() == 1 {
+            return Err(AudioError::InvalidInput(
+                "Cannot remove the only file".to_string(),
+            ));
+        }
+        self.files.remove(index);
+        Ok(())
+    }
+
+    /// Releases the decoded samples of every file while keeping the combiner's
+    /// rate and layout, e.g. when a mixing session ends but the object stays
+    /// referenced. Files can be added again with `add_file`. Calling `free()`
+    /// from JS releases the whole combiner instead.
+    pub fn free_samples(&mut self) {
+        self.files = Vec::new();
+    }
 
-            let probed = symphonia::default::get_probe()
-                .format(&hint, mss, &Default::default(), &Default::default())
-                .map_err(|e| e.to_string())?;
-
-            let mut format = probed.format;
-            let track = format.default_track().ok_or("No supported audio track")?;
-            let mut decoder = symphonia::default::get_codecs()
-                .make(&track.codec_params, &Default::default())
-                .map_err(|e| e.to_string())?;
-
-            let mut sample_buf = None;
-
-            while let Ok(packet) = format.next_packet() {
-                let decoded = decoder.decode(&packet).map_err(|e| e.to_string())?;
-                let spec = *decoded.spec();
-                let num_channels = spec.channels.count();
-
-                let buf = sample_buf.get_or_insert_with(|| {
-                    symphonia::core::audio::SampleBuffer::<f32>::new(
-                        decoded.capacity() as u64,
-                        spec,
-                    )
-                });
-                buf.copy_interleaved_ref(decoded);
-
-                // Convert everything to Stereo (2 channels) during ingestion
-                for frame in buf.samples().chunks(num_channels) {
-                    if num_channels == 1 {
-                        decoded_samples.push(frame[0]); // Left
-                        decoded_samples.push(frame[0]); // Right
-                    } else {
-                        decoded_samples.push(frame[0]); // Left
-                        decoded_samples.push(frame[1]); // Right
+    /// Mixes all files, scaling each by the matching linear gain in `volumes`
+    /// (`1.0` is unity, `0.0` mutes, values above `1.0` boost).
+    ///
+    /// The order of the files only affects the order in which samples are
+    /// summed, so reordering them (with their settings) changes the mix by no
+    /// more than float rounding.
+    pub fn combine(&self, volumes: Vec<f32>) -> Result<CombinedAudioFile, AudioError> {
+        self.combine_with(&MixOptions::new(volumes))
+    }
+
+    /// Like `combine`, but with each file's gain in decibels: `0.0` is unity,
+    /// `-6.0` roughly halves the level and negative infinity mutes.
+    pub fn combine_db(&self, gains_db: Vec<f32>) -> Result<CombinedAudioFile, AudioError> {
+        let volumes = gains_db.iter().map(|&db| db_to_linear(db)).collect();
+        self.combine(volumes)
+    }
+
+    pub fn combine_with(&self, options: &MixOptions) -> Result<CombinedAudioFile, AudioError> {
+        if let Some(window) = options.window_frames {
+            return self.combine_windowed(options, window as usize);
+        }
+
+        let plan = self.plan(options)?;
+        self.check_budget(
+            options,
+            &plan,
+            plan.frames,
+            self.wav_size(options, &plan, plan.frames),
+        )?;
+        let mut master_buffer = Vec::new();
+        let clipped = self.mix_into(options, &plan, &mut master_buffer)?;
+        let channels = plan.output_channels;
+
+        // Wrap in WAV container
+        let frames = master_buffer.len() / channels;
+        let mut bytes = wav::create_wav_container(
+            &master_buffer,
+            self.sample_rate,
+            channels as u16,
+            options.sample_format,
+            options.dither,
+        );
+        wav::write_markers(&mut bytes, &self.markers(options, 0, frames));
+        Ok(CombinedAudioFile::wav(
+            bytes,
+            self.sample_rate,
+            channels,
+            frames,
+            clipped,
+        ))
+    }
+
+    /// Mixes the files into separate stems, e.g. a music bus and a voice bus,
+    /// where `groups` gives the stem of each file. Stem `n` holds the files
+    /// assigned to group `n`, with their settings from `options`. Every stem
+    /// has the length of the full mix so they line up, and a group without
+    /// files comes out silent. Group ids must be below the number of files.
+    pub fn combine_groups(
+        &self,
+        options: &MixOptions,
+        groups: Vec<u32>,
+    ) -> Result<Vec<CombinedAudioFile>, AudioError> {
+        if groups.len() != self.files.len() {
+            return Err(AudioError::InvalidInput(format!(
+                "Got {} groups for {} files, expected one group per file",
+                groups.len(),
+                self.files.len()
+            )));
+        }
+        if let Some(&group) = groups.iter().find(|&&g| g as usize >= self.files.len()) {
+            return Err(AudioError::InvalidInput(format!(
+                "Group {} is out of range, expected ids below {}",
+                group,
+                self.files.len()
+            )));
+        }
+
+        let stems = groups.iter().max().map_or(0, |&max| max + 1);
+        (0..stems)
+            .map(|stem| {
+                let mut options = options.clone();
+                for (i, &group) in groups.iter().enumerate() {
+                    if group != stem {
+                        options.set_muted(i, true);
                     }
                 }
+                self.combine_with(&options)
+            })
+            .collect()
+    }
+
+    /// Mixes only the part of the timeline from `start_ms` to `end_ms`, e.g.
+    /// to preview a few seconds while scrubbing without rendering the whole
+    /// mix. Both ends are clamped to the mix. The compressor and
+    /// normalization only see the rendered range, so they can differ slightly
+    /// from the same stretch of a full mix.
+    pub fn combine_range(
+        &self,
+        options: &MixOptions,
+        start_ms: f64,
+        end_ms: f64,
+    ) -> Result<CombinedAudioFile, AudioError> {
+        let plan = self.plan(options)?;
+        let start = self.ms_to_frames(start_ms).min(plan.frames);
+        let end = self.ms_to_frames(end_ms).clamp(start, plan.frames);
+        self.check_budget(
+            options,
+            &plan,
+            end - start,
+            self.wav_size(options, &plan, end - start),
+        )?;
+        let mut master_buffer = plan.render(start, end - start)?;
+        let clipped = self.master(options, plan.output_channels, &mut master_buffer);
+
+        let mut bytes = wav::create_wav_container(
+            &master_buffer,
+            self.sample_rate,
+            plan.output_channels as u16,
+            options.sample_format,
+            options.dither,
+        );
+        wav::write_markers(&mut bytes, &self.markers(options, start, end));
+        Ok(CombinedAudioFile::wav(
+            bytes,
+            self.sample_rate,
+            plan.output_channels,
+            end - start,
+            clipped,
+        ))
+    }
+
+    /// Mixes only file `index` with its settings from `options`, muting every
+    /// other file, for auditioning a single track.
+    pub fn solo(
+        &self,
+        index: usize,
+        options: &MixOptions,
+    ) -> Result<CombinedAudioFile, AudioError> {
+        self.file(index)?;
+        let mut options = options.clone();
+        for i in (0..self.files.len()).filter(|&i| i != index) {
+            options.set_muted(i, true);
+        }
+        self.combine_with(&options)
+    }
+
+    /// Like `combine`, but returns the interleaved samples without a WAV
+    /// container, for feeding a Web Audio `AudioBuffer` directly.
+    pub fn combine_raw(&self, volumes: Vec<f32>) -> Result<Vec<f32>, AudioError> {
+        self.combine_raw_with(&MixOptions::new(volumes))
+    }
+
+    pub fn combine_raw_with(&self, options: &MixOptions) -> Result<Vec<f32>, AudioError> {
+        let plan = self.plan(options)?;
+        // Deinterleaving copies the output once more
+        let output_bytes = match options.layout {
+            SampleLayout::Interleaved => 0,
+            SampleLayout::Planar => plan.frames as u64 * plan.output_channels as u64 * 4,
+        };
+        self.check_budget(options, &plan, plan.frames, output_bytes)?;
+        let mut samples = Vec::new();
+        self.mix_into(options, &plan, &mut samples)?;
+        Ok(match options.layout {
+            SampleLayout::Interleaved => samples,
+            SampleLayout::Planar => channels::deinterleave(&samples, plan.output_channels),
+        })
+    }
+
+    /// Plays the files back to back instead of together, overlapping each
+    /// consecutive pair by `fade_ms` with complementary linear fades.
+    pub fn concat_crossfade(&self, fade_ms: f64) -> Result<CombinedAudioFile, AudioError> {
+        let fade = self.ms_to_frames(fade_ms);
+        let lengths: Vec<usize> = self
+            .files
+            .iter()
+            .map(|f| f.samples.len() / self.channels)
+            .collect();
+
+        let mut tracks = Vec::with_capacity(self.files.len());
+        let mut offset = 0;
+        let mut fade_in = 0;
+        for (i, file) in self.files.iter().enumerate() {
+            let frames = lengths[i];
+            // A fade ends before the next one starts, so at most two files
+            // overlap and their fades stay complementary. A middle file keeps
+            // half of itself for its own fade out.
+            let fade_out = lengths.get(i + 1).map_or(0, |&next| {
+                let room = if i + 2 == lengths.len() {
+                    next
+                } else {
+                    next / 2
+                };
+                fade.min(frames - fade_in).min(room)
+            });
+            tracks.push(mix::Track {
+                samples: &file.samples,
+                offset,
+                gains: vec![1.0; self.channels],
+                fade: envelope::Fade {
+                    len: frames,
+                    fade_in,
+                    fade_out,
+                },
+                looping: false,
+                width: 1.0,
+                automation: Default::default(),
+            });
+            offset += frames - fade_out;
+            fade_in = fade_out;
+        }
+
+        let plan = mix::MixPlan {
+            frames: tracks.last().map_or(0, |t| t.offset + t.fade.len),
+            tracks,
+            channels: self.channels,
+            output_channels: self.channels,
+            cancel: Default::default(),
+            high_precision: false,
+            master_gain: 1.0,
+        };
+        let master_buffer = plan.render(0, plan.frames)?;
+        let bytes = wav::create_wav_container(
+            &master_buffer,
+            self.sample_rate,
+            self.channels as u16,
+            SampleFormat::Pcm16,
+            Dither::None,
+        );
+        Ok(CombinedAudioFile::wav(
+            bytes,
+            self.sample_rate,
+            self.channels,
+            plan.frames,
+            dynamics::overs(&master_buffer),
+        ))
+    }
+
+    /// Turns this combiner into a `MixSession` for repeated re-mixing with the
+    /// given `options`, e.g. while a volume slider moves. The combiner is
+    /// consumed; `MixSession::finish` hands it back.
+    pub fn start_session(self, options: MixOptions) -> MixSession {
+        MixSession::new(self, options)
+    }
+
+    /// Sample rate of the mixed output in Hz.
+    #[wasm_bindgen(getter)]
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Number of interleaved channels in the mixed output.
+    #[wasm_bindgen(getter)]
+    pub fn channels(&self) -> u32 {
+        self.channels as u32
+    }
+
+    /// Number of files in the combiner, and so the length `volumes` needs.
+    #[wasm_bindgen(getter)]
+    pub fn file_count(&self) -> u32 {
+        self.files.len() as u32
+    }
+
+    /// Samples per channel of file `index` at the combiner's rate.
+    pub fn file_sample_count(&self, index: usize) -> Result<u32, AudioError> {
+        Ok((self.file(index)?.samples.len() / self.channels) as u32)
+    }
+
+    /// Decoded length and source format of file `index`.
+    pub fn file_info(&self, index: usize) -> Result<FileInfo, AudioError> {
+        let file = self.file(index)?;
+        let frames = file.samples.len() / self.channels;
+        Ok(FileInfo {
+            sample_count: frames as u32,
+            duration_seconds: frames as f64 / self.sample_rate as f64,
+            sample_rate: file.sample_rate,
+            channels: file.source_channels as u32,
+            skipped_packets: file.skipped_packets,
+        })
+    }
+
+    /// Whether the files were decoded at different sample rates. They are
+    /// resampled to the combiner's rate either way, but a UI may want to
+    /// point out that some were converted. Each file's source rate is in
+    /// `file_info`.
+    pub fn has_mixed_sample_rates(&self) -> bool {
+        self.files
+            .windows(2)
+            .any(|pair| pair[0].sample_rate != pair[1].sample_rate)
+    }
+
+    /// Min/max peaks of file `index` for drawing its waveform, as `buckets`
+    /// interleaved `[min, max]` pairs spread evenly over the file.
+    pub fn waveform(&self, index: usize, buckets: usize) -> Result<Vec<f32>, AudioError> {
+        let file = self.file(index)?;
+        Ok(analysis::waveform(&file.samples, self.channels, buckets))
+    }
+
+    /// Integrated loudness of file `index` in LUFS per ITU-R BS.1770. Silent
+    /// files and files shorter than 400 ms measure as negative infinity.
+    pub fn loudness(&self, index: usize) -> Result<f64, AudioError> {
+        let file = self.file(index)?;
+        Ok(loudness::integrated_loudness(
+            &file.samples,
+            self.channels,
+            self.sample_rate,
+        ))
+    }
+
+    /// Applies a gain to file `index` so its integrated loudness becomes
+    /// `target_lufs`, e.g. `-16.0` to even out spoken tracks before mixing.
+    /// The decoded samples are changed in place, so call it once per target.
+    /// Files that cannot be measured are left untouched.
+    pub fn normalize_loudness(&mut self, index: usize, target_lufs: f64) -> Result<(), AudioError> {
+        let measured = self.loudness(index)?;
+        if !measured.is_finite() {
+            return Ok(());
+        }
+        let gain = 10f64.powf((target_lufs - measured) / 20.0) as f32;
+        let file = &mut self.files[index];
+        for sample in file.samples.iter_mut() {
+            *sample *= gain;
+        }
+        file.levels = analysis::levels(&file.samples);
+        Ok(())
+    }
+
+    /// Filters file `index` in place with a 12 dB/octave biquad, e.g. a
+    /// high-pass around 80 Hz to remove rumble from a voice track. `q` of
+    /// about `0.707` gives a flat passband.
+    pub fn apply_filter(
+        &mut self,
+        index: usize,
+        filter_type: FilterType,
+        cutoff_hz: f64,
+        q: f64,
+    ) -> Result<(), AudioError> {
+        self.file(index)?;
+        let nyquist = self.sample_rate as f64 / 2.0;
+        if !(cutoff_hz > 0.0 && cutoff_hz < nyquist) {
+            return Err(AudioError::InvalidInput(format!(
+                "Cutoff must be between 0 and {} Hz, got {}",
+                nyquist, cutoff_hz
+            )));
+        }
+        if q.is_nan() || q <= 0.0 {
+            return Err(AudioError::InvalidInput(format!(
+                "Q must be greater than 0, got {}",
+                q
+            )));
+        }
+
+        let biquad = match filter_type {
+            FilterType::LowPass => filter::Biquad::low_pass(self.sample_rate, cutoff_hz, q),
+            FilterType::HighPass => filter::Biquad::high_pass(self.sample_rate, cutoff_hz, q),
+        };
+        let file = &mut self.files[index];
+        filter::apply(&mut file.samples, self.channels, &[biquad]);
+        file.levels = analysis::levels(&file.samples);
+        Ok(())
+    }
+
+    /// Applies a 3-band EQ to file `index` in place: a low shelf at 250 Hz, a
+    /// mid peak at 1 kHz and a high shelf at 4 kHz, each boosting or cutting by
+    /// the given gain in dB. `0.0` leaves a band untouched, and gains are
+    /// limited to ±24 dB.
+    pub fn apply_eq(
+        &mut self,
+        index: usize,
+        low_db: f64,
+        mid_db: f64,
+        high_db: f64,
+    ) -> Result<(), AudioError> {
+        self.file(index)?;
+        for gain_db in [low_db, mid_db, high_db] {
+            if !(-MAX_EQ_GAIN_DB..=MAX_EQ_GAIN_DB).contains(&gain_db) {
+                return Err(AudioError::InvalidInput(format!(
+                    "EQ gain must be within ±{} dB, got {}",
+                    MAX_EQ_GAIN_DB, gain_db
+                )));
+            }
+        }
+        let filters = filter::three_band_eq(self.sample_rate, low_db, mid_db, high_db);
+        if filters.is_empty() {
+            return Ok(());
+        }
+        let file = &mut self.files[index];
+        filter::apply(&mut file.samples, self.channels, &filters);
+        file.levels = analysis::levels(&file.samples);
+        Ok(())
+    }
+
+    /// Removes any DC offset from file `index` in place by subtracting each
+    /// channel's mean, regaining headroom lost to a biased recording.
+    pub fn remove_dc_offset(&mut self, index: usize) -> Result<(), AudioError> {
+        self.file(index)?;
+        let file = &mut self.files[index];
+        filter::remove_dc(&mut file.samples, self.channels);
+        file.levels = analysis::levels(&file.samples);
+        Ok(())
+    }
+
+    /// Cuts the silence at the start (`head`) and/or end (`tail`) of file
+    /// `index` in place, so layered files line up on their first sound. Frames
+    /// where every channel stays below `threshold_db` (e.g. `-60.0`) count as
+    /// silent; whole frames are removed so channels stay in sync. A file that
+    /// is silent throughout becomes empty.
+    pub fn trim_silence(
+        &mut self,
+        index: usize,
+        threshold_db: f32,
+        head: bool,
+        tail: bool,
+    ) -> Result<(), AudioError> {
+        self.file(index)?;
+        let threshold = 10f32.powf(threshold_db / 20.0);
+        let file = &mut self.files[index];
+        let frames = file.samples.len() / self.channels;
+        let (first, last) = analysis::audible_frames(&file.samples, self.channels, threshold);
+        let silent = first == last;
+        let start = if head && !silent { first } else { 0 };
+        let end = if tail || (head && silent) {
+            last
+        } else {
+            frames
+        };
+        file.samples.truncate(end * self.channels);
+        file.samples.drain(..start * self.channels);
+        file.levels = analysis::levels(&file.samples);
+        Ok(())
+    }
+
+    /// Reverses file `index` in place, frame by frame so channels stay paired.
+    pub fn reverse(&mut self, index: usize) -> Result<(), AudioError> {
+        self.file(index)?;
+        let samples = &mut self.files[index].samples;
+        samples.reverse();
+        for frame in samples.chunks_exact_mut(self.channels) {
+            frame.reverse();
+        }
+        Ok(())
+    }
+
+    /// Swaps the left and right channels of file `index` in place, for
+    /// recordings wired the wrong way round. Calling it again undoes it.
+    pub fn swap_channels(&mut self, index: usize) -> Result<(), AudioError> {
+        self.file(index)?;
+        if self.channels != 2 {
+            return Err(AudioError::InvalidInput(format!(
+                "Swapping channels needs stereo files, but they have {} channels",
+                self.channels
+            )));
+        }
+        for frame in self.files[index].samples.chunks_exact_mut(2) {
+            frame.swap(0, 1);
+        }
+        Ok(())
+    }
+
+    /// Changes the tempo of file `index` in place without changing its pitch.
+    /// `factor` is the speed: `1.1` plays 10% faster and makes the file
+    /// shorter, `1.0` leaves it untouched. `factor` must be between `0.25`
+    /// and `4.0`. This is CPU-heavy, so it only runs when called.
+    pub fn time_stretch(&mut self, index: usize, factor: f64) -> Result<(), AudioError> {
+        self.file(index)?;
+        if !SPEED_FACTORS.contains(&factor) {
+            return Err(AudioError::InvalidInput(format!(
+                "Stretch factor must be between {} and {}, got {}",
+                SPEED_FACTORS.start(),
+                SPEED_FACTORS.end(),
+                factor
+            )));
+        }
+        if factor == 1.0 {
+            return Ok(());
+        }
+        let file = &mut self.files[index];
+        file.samples = stretch::wsola(&file.samples, self.channels, self.sample_rate, factor);
+        file.levels = analysis::levels(&file.samples);
+        Ok(())
+    }
+
+    /// Plays file `index` back at `factor` times its speed, changing pitch and
+    /// duration together like a tape or turntable: `2.0` is twice as fast
+    /// and an octave higher, `1.0` leaves it untouched. Like `time_stretch`,
+    /// `factor` must be between `0.25` and `4.0`. A plain resample, so much
+    /// cheaper than `time_stretch` or `pitch_shift`.
+    pub fn change_speed(&mut self, index: usize, factor: f64) -> Result<(), AudioError> {
+        self.file(index)?;
+        if !SPEED_FACTORS.contains(&factor) {
+            return Err(AudioError::InvalidInput(format!(
+                "Speed factor must be between {} and {}, got {}",
+                SPEED_FACTORS.start(),
+                SPEED_FACTORS.end(),
+                factor
+            )));
+        }
+        if factor == 1.0 {
+            return Ok(());
+        }
+        // Treating the samples as recorded at a higher rate plays them faster
+        let source_rate = (self.sample_rate as f64 * factor).round() as u32;
+        let file = &mut self.files[index];
+        file.samples =
+            resample::resample_linear(&file.samples, self.channels, source_rate, self.sample_rate);
+        file.levels = analysis::levels(&file.samples);
+        Ok(())
+    }
+
+    /// Shifts the pitch of file `index` in place by `semitones` (negative
+    /// lowers it) while keeping its duration, by time-stretching and then
+    /// resampling. All channels are stretched at the same offsets, so the
+    /// stereo image holds. `0.0` leaves the file untouched, and shifts are
+    /// limited to four octaves (48 semitones) either way. CPU-heavy like
+    /// `time_stretch`.
+    pub fn pitch_shift(&mut self, index: usize, semitones: f64) -> Result<(), AudioError> {
+        self.file(index)?;
+        if !(-MAX_PITCH_SHIFT_SEMITONES..=MAX_PITCH_SHIFT_SEMITONES).contains(&semitones) {
+            return Err(AudioError::InvalidInput(format!(
+                "Pitch shift must be within ±{} semitones, got {}",
+                MAX_PITCH_SHIFT_SEMITONES, semitones
+            )));
+        }
+        if semitones == 0.0 {
+            return Ok(());
+        }
+
+        let ratio = 2f64.powf(semitones / 12.0);
+        let file = &mut self.files[index];
+        let frames = file.samples.len() / self.channels;
+        let stretched = stretch::wsola(&file.samples, self.channels, self.sample_rate, 1.0 / ratio);
+        // Playing the longer audio back faster restores the duration and
+        // raises the pitch by the same ratio
+        let stretched_rate = (self.sample_rate as f64 * ratio).round().max(1.0) as u32;
+        file.samples =
+            resample::resample_linear(&stretched, self.channels, stretched_rate, self.sample_rate);
+        file.samples.resize(frames * self.channels, 0.0);
+        file.levels = analysis::levels(&file.samples);
+        Ok(())
+    }
+
+    /// Tags read from file `index`, empty when it has none.
+    pub fn metadata(&self, index: usize) -> Result<Vec<MetadataTag>, AudioError> {
+        Ok(self.file(index)?.metadata.clone())
+    }
+
+    /// Ducks file `target` in place whenever file `trigger` is louder than
+    /// `threshold_db` (dBFS), reducing the target by `ratio` like a sidechain
+    /// compressor, e.g. to lower music under a voice. Both files are lined up
+    /// from their first sample; offsets given at combine time are not taken
+    /// into account.
+    pub fn duck(
+        &mut self,
+        trigger: usize,
+        target: usize,
+        threshold_db: f32,
+        ratio: f32,
+        attack_ms: f32,
+        release_ms: f32,
+    ) -> Result<(), AudioError> {
+        self.file(trigger)?;
+        self.file(target)?;
+        if trigger == target {
+            return Err(AudioError::InvalidInput(
+                "A file cannot duck itself".to_string(),
+            ));
+        }
+        if ratio.is_nan() || ratio < 1.0 {
+            return Err(AudioError::InvalidInput(format!(
+                "Ratio must be at least 1, got {}",
+                ratio
+            )));
+        }
+
+        let mut computer = dynamics::GainComputer::new(
+            self.sample_rate,
+            threshold_db,
+            ratio,
+            attack_ms,
+            release_ms,
+        );
+        let channels = self.channels;
+        let levels: Vec<f32> = self.files[trigger]
+            .samples
+            .chunks_exact(channels)
+            .map(|frame| frame.iter().fold(0.0f32, |peak, s| peak.max(s.abs())))
+            .collect();
+        let file = &mut self.files[target];
+        for (i, frame) in file.samples.chunks_exact_mut(channels).enumerate() {
+            // Past the end of the trigger the gain releases back to unity
+            let gain = computer.next(levels.get(i).copied().unwrap_or(0.0));
+            frame.iter_mut().for_each(|s| *s *= gain);
+        }
+        file.levels = analysis::levels(&file.samples);
+        Ok(())
+    }
+
+    /// Gates file `index` in place, silencing it wherever its own level stays
+    /// below `threshold_db` (dBFS), e.g. to cut room tone between words of a
+    /// voice track. The gate stays open for `hold_ms` after the level drops,
+    /// then fades out over `release_ms`; it fades back in over `attack_ms`.
+    pub fn gate(
+        &mut self,
+        index: usize,
+        threshold_db: f32,
+        attack_ms: f32,
+        release_ms: f32,
+        hold_ms: f32,
+    ) -> Result<(), AudioError> {
+        self.file(index)?;
+        let mut gate = dynamics::Gate::new(
+            self.sample_rate,
+            threshold_db,
+            attack_ms,
+            release_ms,
+            hold_ms,
+        );
+        let file = &mut self.files[index];
+        for frame in file.samples.chunks_exact_mut(self.channels) {
+            let level = frame.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+            let gain = gate.next(level);
+            frame.iter_mut().for_each(|s| *s *= gain);
+        }
+        file.levels = analysis::levels(&file.samples);
+        Ok(())
+    }
+
+    /// Peak and RMS level of each decoded file, in file order.
+    pub fn file_levels(&self) -> Vec<FileLevels> {
+        self.files.iter().map(|f| f.levels).collect()
+    }
+}
+
+impl AudioCombiner {
+    fn from_sources(
+        sources: impl ExactSizeIterator<Item = (Vec<u8>, Option<SingleAudioFileType>)>,
+        target_sample_rate: Option<u32>,
+        on_progress: Option<js_sys::Function>,
+        channel_mode: Option<ChannelMode>,
+        cancel_flag: Option<js_sys::Int32Array>,
+    ) -> Result<AudioCombiner, AudioError> {
+        let channel_mode = channel_mode.unwrap_or(ChannelMode::Stereo);
+        let cancel = cancel::Cancel::new(cancel_flag.as_ref());
+        check_sources(target_sample_rate, sources.len())?;
+
+        let mut processed_files = Vec::with_capacity(sources.len());
+
+        for (i, (bytes, file_type)) in sources.enumerate() {
+            let decoded = decode::decode_file(bytes, file_type, channel_mode, cancel, |fraction| {
+                report_progress(&on_progress, i, fraction)
+            })
+            .map_err(|e| e.in_file(i))?;
+            processed_files.push(decoded);
+        }
+
+        Self::assemble(processed_files, target_sample_rate, channel_mode, cancel)
+    }
+
+    /// Builds a combiner from freshly decoded files, conforming them to a
+    /// common rate and layout.
+    fn assemble(
+        processed_files: Vec<AudioCombinerSingleFile>,
+        target_sample_rate: Option<u32>,
+        channel_mode: ChannelMode,
+        cancel: cancel::Cancel,
+    ) -> Result<AudioCombiner, AudioError> {
+        if processed_files.iter().all(|f| f.samples.is_empty()) {
+            return Err(AudioError::InvalidInput(
+                "None of the files contain any audio samples".to_string(),
+            ));
+        }
+
+        let sample_rate = target_sample_rate
+            .or_else(|| processed_files.iter().map(|f| f.sample_rate).max())
+            .unwrap_or(DEFAULT_SAMPLE_RATE);
+
+        let channels = processed_files
+            .iter()
+            .map(|f| f.channels)
+            .max()
+            .unwrap_or(2);
+
+        let mut combiner = AudioCombiner {
+            files: Vec::with_capacity(processed_files.len()),
+            sample_rate,
+            channels,
+            channel_mode,
+        };
+        for mut file in processed_files {
+            cancel.check()?;
+            combiner.conform(&mut file);
+            combiner.files.push(file);
+        }
+        Ok(combiner)
+    }
+
+    fn from_decoded(
+        buffers: impl ExactSizeIterator<Item = Vec<f32>>,
+        sample_rate: u32,
+        channels: usize,
+    ) -> Result<AudioCombiner, AudioError> {
+        if sample_rate == 0 {
+            return Err(AudioError::InvalidInput(
+                "Sample rate must be greater than 0".to_string(),
+            ));
+        }
+        if channels == 0 {
+            return Err(AudioError::InvalidInput(
+                "Channel count must be greater than 0".to_string(),
+            ));
+        }
+        if buffers.len() == 0 {
+            return Err(AudioError::InvalidInput(
+                "No files were provided, at least one is needed".to_string(),
+            ));
+        }
+
+        utils::set_panic_hook();
+
+        let mut files = Vec::with_capacity(buffers.len());
+        for (i, mut samples) in buffers.enumerate() {
+            if samples.len() % channels != 0 {
+                return Err(AudioError::InvalidInput(format!(
+                    "Got {} samples, which is not a whole number of {} channel frames",
+                    samples.len(),
+                    channels
+                ))
+                .in_file(i));
             }
-            processed_files.push(AudioCombinerSingleFile {
-                samples: decoded_samples,
+            decode::sanitize(&mut samples);
+            files.push(AudioCombinerSingleFile {
+                levels: analysis::levels(&samples),
+                samples,
+                sample_rate,
+                channels,
+                source_channels: channels,
+                skipped_packets: 0,
+                metadata: Vec::new(),
             });
         }
+        if files.iter().all(|f| f.samples.is_empty()) {
+            return Err(AudioError::InvalidInput(
+                "None of the files contain any audio samples".to_string(),
+            ));
+        }
 
         Ok(AudioCombiner {
-            files: processed_files,
+            files,
+            sample_rate,
+            channels,
+            channel_mode: ChannelMode::Passthrough,
         })
     }
 
-    pub fn combine(&self, volumes: Vec<u8>) -> Result<SingleAudioFile, String> {
-        let target_sample_rate = 44100u32;
+    /// Brings a decoded file to the common rate and layout so it stays in sync
+    /// when mixed.
+    fn conform(&self, file: &mut AudioCombinerSingleFile) {
+        file.samples = resample::resample_linear(
+            &file.samples,
+            file.channels,
+            file.sample_rate,
+            self.sample_rate,
+        );
+        file.samples = channels::expand_channels(&file.samples, file.channels, self.channels);
+        file.levels = analysis::levels(&file.samples);
+    }
 
-        // 1. Determine final length
-        let max_len = self
-            .files
+    fn file(&self, index: usize) -> Result<&AudioCombinerSingleFile, AudioError> {
+        self.files.get(index).ok_or_else(|| {
+            AudioError::InvalidInput(format!(
+                "File index {} is out of range for {} files",
+                index,
+                self.files.len()
+            ))
+        })
+    }
+
+    /// Converts a duration in milliseconds to a frame count.
+    fn ms_to_frames(&self, ms: f64) -> usize {
+        (ms.max(0.0) * self.sample_rate as f64 / 1000.0).round() as usize
+    }
+
+    /// Converts a duration in milliseconds to an interleaved sample count.
+    fn ms_to_samples(&self, ms: f64) -> usize {
+        self.ms_to_frames(ms).saturating_mul(self.channels)
+    }
+
+    /// The markers of `options` that fall within timeline frames
+    /// `start..=end`, as `(frame, label)` relative to `start`.
+    fn markers<'a>(
+        &self,
+        options: &'a MixOptions,
+        start: usize,
+        end: usize,
+    ) -> Vec<(u32, &'a str)> {
+        options
+            .markers
             .iter()
-            .map(|f| f.samples.len())
-            .max()
-            .unwrap_or(0);
+            .map(|(ms, label)| (self.ms_to_frames(*ms), label.as_str()))
+            .filter(|&(frame, _)| (start..=end).contains(&frame))
+            .map(|(frame, label)| ((frame - start) as u32, label))
+            .collect()
+    }
 
-        // 2. Pre-allocate master buffer with zeros
-        let mut master_buffer = vec![0.0f32; max_len];
+    /// The part of file `i` selected by the trim settings in `options`.
+    fn trimmed_samples(&self, i: usize, options: &MixOptions) -> &[f32] {
+        let samples = &self.files[i].samples;
+        let end = options
+            .trim_ends_ms
+            .get(i)
+            .map_or(samples.len(), |&ms| self.ms_to_samples(ms))
+            .min(samples.len());
+        let start = options
+            .trim_starts_ms
+            .get(i)
+            .map_or(0, |&ms| self.ms_to_samples(ms))
+            .min(end);
+        &samples[start..end]
+    }
 
-        // 3. Simple addition mix
-        for (i, file) in self.files.iter().enumerate() {
-            let volume_factor = *volumes.get(i).unwrap_or(&100) as f32 / 100.0;
+    fn output_channels(&self, options: &MixOptions) -> Result<usize, AudioError> {
+        match options.output_channels {
+            None => Ok(self.channels),
+            Some(1) => Ok(1),
+            Some(n) if n as usize == self.channels => Ok(self.channels),
+            Some(n) => Err(AudioError::InvalidInput(format!(
+                "Cannot output {} channels from a {} channel mix",
+                n, self.channels
+            ))),
+        }
+    }
+
+    fn plan<'a>(&'a self, options: &'a MixOptions) -> Result<mix::MixPlan<'a>, AudioError> {
+        let output_channels = self.output_channels(options)?;
+
+        if self.files.is_empty() {
+            return Err(AudioError::InvalidInput(
+                "No files to mix, add a file first".to_string(),
+            ));
+        }
+        if options.volumes.len() != self.files.len() {
+            return Err(AudioError::InvalidInput(format!(
+                "Got {} volumes for {} files, expected one volume per file",
+                options.volumes.len(),
+                self.files.len()
+            )));
+        }
+
+        if self.channels != 2 && !options.pans.is_empty() {
+            return Err(AudioError::InvalidInput(format!(
+                "Panning needs stereo output, but the mix has {} channels",
+                self.channels
+            )));
+        }
+        if output_channels != 2 && options.crossfeed.is_some() {
+            return Err(AudioError::InvalidInput(format!(
+                "Crossfeed needs stereo output, but the mix has {} channels",
+                output_channels
+            )));
+        }
+        if self.channels != 2 && !options.widths.is_empty() {
+            return Err(AudioError::InvalidInput(format!(
+                "Stereo width needs stereo output, but the mix has {} channels",
+                self.channels
+            )));
+        }
+
+        if let Some(&offset) = options.offsets_ms.iter().find(|ms| !ms.is_finite()) {
+            return Err(AudioError::InvalidInput(format!(
+                "Offsets must be finite, got {}",
+                offset
+            )));
+        }
+        if !options.fixed_length_ms.is_finite() {
+            return Err(AudioError::InvalidInput(format!(
+                "Fixed length must be finite, got {}",
+                options.fixed_length_ms
+            )));
+        }
+
+        let mut tracks: Vec<mix::Track> = (0..self.files.len())
+            .map(|i| {
+                let samples = self.trimmed_samples(i, options);
+                let polarity = if *options.inverted.get(i).unwrap_or(&false) {
+                    -1.0
+                } else {
+                    1.0
+                };
+                let volume_factor = options.volumes[i] * polarity;
+                let gains = if self.channels == 2 {
+                    let (left_pan, right_pan) = pan_gains(*options.pans.get(i).unwrap_or(&0.0));
+                    vec![volume_factor * left_pan, volume_factor * right_pan]
+                } else {
+                    vec![volume_factor; self.channels]
+                };
+                mix::Track {
+                    samples,
+                    offset: self.ms_to_frames(*options.offsets_ms.get(i).unwrap_or(&0.0)),
+                    gains,
+                    fade: envelope::Fade {
+                        len: samples.len() / self.channels,
+                        fade_in: self.ms_to_frames(*options.fade_ins_ms.get(i).unwrap_or(&0.0)),
+                        fade_out: self.ms_to_frames(*options.fade_outs_ms.get(i).unwrap_or(&0.0)),
+                    },
+                    looping: *options.looping.get(i).unwrap_or(&false),
+                    width: *options.widths.get(i).unwrap_or(&1.0),
+                    automation: envelope::Automation {
+                        points: options.automation.get(i).map_or(Vec::new(), |points| {
+                            points
+                                .iter()
+                                .map(|&(ms, gain)| (self.ms_to_frames(ms), gain))
+                                .collect()
+                        }),
+                    },
+                }
+            })
+            .collect();
 
-            // Zip allows the compiler to use SIMD optimizations
-            for (m_sample, &f_sample) in master_buffer.iter_mut().zip(file.samples.iter()) {
-                *m_sample += f_sample * volume_factor;
+        let too_long = || {
+            AudioError::InvalidInput(
+                "The mix is too long, check offsets_ms and fixed_length_ms".to_string(),
+            )
+        };
+        let track_frames = |t: &mix::Track| t.samples.len() / self.channels;
+        if let Alignment::End = options.alignment {
+            let longest = tracks.iter().map(track_frames).max().unwrap_or(0);
+            for track in tracks.iter_mut() {
+                track.offset = track
+                    .offset
+                    .checked_add(longest - track_frames(track))
+                    .ok_or_else(too_long)?;
             }
         }
 
-        // 4. Wrap in WAV container
-        Ok(SingleAudioFile {
-            bytes: create_wav_container(&master_buffer, target_sample_rate),
-            r#type: SingleAudioFileType::Wav,
+        let track_ends = tracks
+            .iter()
+            .map(|t| t.offset.checked_add(track_frames(t)).ok_or_else(too_long))
+            .collect::<Result<Vec<_>, _>>()?;
+        let frames = match options.length_policy {
+            LengthPolicy::Longest => track_ends.iter().copied().max().unwrap_or(0),
+            LengthPolicy::Shortest => track_ends.iter().copied().min().unwrap_or(0),
+            LengthPolicy::Fixed => self.ms_to_frames(options.fixed_length_ms),
+        };
+        // Sample counts and positions are 32-bit in WAV and in wasm memory
+        if frames as u64 > u32::MAX as u64 / self.channels as u64 {
+            return Err(too_long());
+        }
+
+        // Looping files run to the end of the mix, so fade them out there
+        for track in tracks.iter_mut().filter(|t| t.looping) {
+            track.fade.len = frames.saturating_sub(track.offset);
+        }
+
+        let tracks = tracks
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !options.muted.get(*i).unwrap_or(&false))
+            .map(|(_, track)| track)
+            .collect();
+
+        Ok(mix::MixPlan {
+            tracks,
+            channels: self.channels,
+            output_channels,
+            frames,
+            cancel: cancel::Cancel::new(options.cancel_flag.as_ref()),
+            high_precision: options.high_precision,
+            master_gain: options.master_gain.unwrap_or(1.0),
         })
     }
+
+    /// Fails with `TooLarge` when `options` sets a memory budget that a mix
+    /// rendering `buffer_frames` frames at a time into floats, plus
+    /// `output_bytes` of output, would exceed.
+    fn check_budget(
+        &self,
+        options: &MixOptions,
+        plan: &mix::MixPlan,
+        buffer_frames: usize,
+        output_bytes: u64,
+    ) -> Result<(), AudioError> {
+        let budget = match options.memory_budget_bytes {
+            Some(budget) => budget as u64,
+            None => return Ok(()),
+        };
+        // High precision sums into an f64 buffer next to the f32 one
+        let float_bytes = if options.high_precision { 12 } else { 4 };
+        let needed = buffer_frames as u64 * plan.channels as u64 * float_bytes + output_bytes;
+        if needed > budget {
+            return Err(AudioError::TooLarge(format!(
+                "The mix needs about {} bytes but the budget is {} bytes",
+                needed, budget
+            )));
+        }
+        Ok(())
+    }
+
+    /// Size of the WAV encoding `frames` frames of `plan`.
+    fn wav_size(&self, options: &MixOptions, plan: &mix::MixPlan, frames: usize) -> u64 {
+        wav::file_size_u64(
+            frames as u64 * plan.output_channels as u64,
+            plan.output_channels as u16,
+            options.sample_format,
+        )
+    }
+
+    /// Renders `plan` into `master_buffer`, reusing its allocation, and runs
+    /// the master bus over it. Returns how many samples clipped.
+    fn mix_into(
+        &self,
+        options: &MixOptions,
+        plan: &mix::MixPlan,
+        master_buffer: &mut Vec<f32>,
+    ) -> Result<usize, AudioError> {
+        plan.render_into(0, plan.frames, master_buffer)?;
+        Ok(self.master(options, plan.output_channels, master_buffer))
+    }
+
+    /// Runs the master bus processing of `options` over a rendered buffer.
+    /// Returns how many samples clipped.
+    fn master(&self, options: &MixOptions, channels: usize, master_buffer: &mut [f32]) -> usize {
+        if let Some(declick) = &options.declick {
+            declick::Declicker::new(declick, self.sample_rate, channels).process(master_buffer);
+        }
+        if let Some(crossfeed) = &options.crossfeed {
+            crossfeed::Crossfeeder::new(crossfeed, self.sample_rate).process(master_buffer);
+        }
+        if let Some(compressor) = &options.compressor {
+            dynamics::MasterCompressor::new(compressor, self.sample_rate, channels)
+                .process(master_buffer);
+        }
+        // Optionally scale down so the loudest sample sits at full scale
+        if options.normalize {
+            normalize_peak(master_buffer);
+        }
+        dynamics::clip(master_buffer, options.clip_mode)
+    }
+
+    /// Renders and encodes the mix one window at a time, so only the WAV bytes
+    /// and a single window of floats are alive at once.
+    fn combine_windowed(
+        &self,
+        options: &MixOptions,
+        window: usize,
+    ) -> Result<CombinedAudioFile, AudioError> {
+        let plan = self.plan(options)?;
+        self.check_budget(
+            options,
+            &plan,
+            plan.frames.min(window.max(1)),
+            self.wav_size(options, &plan, plan.frames),
+        )?;
+        // The declicker, crossfeed and compressor carry their state from
+        // window to window, and each pass starts them afresh so both passes
+        // see the same output
+        let declicker = || {
+            options.declick.as_ref().map(|declick| {
+                declick::Declicker::new(declick, self.sample_rate, plan.output_channels)
+            })
+        };
+        let crossfeeder = || {
+            options
+                .crossfeed
+                .as_ref()
+                .map(|crossfeed| crossfeed::Crossfeeder::new(crossfeed, self.sample_rate))
+        };
+        let compressor = || {
+            options.compressor.as_ref().map(|compressor| {
+                dynamics::MasterCompressor::new(compressor, self.sample_rate, plan.output_channels)
+            })
+        };
+
+        // Normalizing needs the peak of the whole mix, so take an extra pass
+        let mut peak = 0.0f32;
+        if options.normalize {
+            let mut declicker = declicker();
+            let mut crossfeeder = crossfeeder();
+            let mut compressor = compressor();
+            plan.for_each_window(window, |mut buffer| {
+                if let Some(declicker) = &mut declicker {
+                    declicker.process(&mut buffer);
+                }
+                if let Some(crossfeeder) = &mut crossfeeder {
+                    crossfeeder.process(&mut buffer);
+                }
+                if let Some(compressor) = &mut compressor {
+                    compressor.process(&mut buffer);
+                }
+                peak = buffer.iter().fold(peak, |peak, s| peak.max(s.abs()));
+            })?;
+        }
+
+        let sample_count = plan.frames * plan.output_channels;
+        let mut bytes = Vec::with_capacity(wav::file_size(
+            sample_count,
+            plan.output_channels as u16,
+            options.sample_format,
+        ));
+        wav::write_header(
+            &mut bytes,
+            sample_count,
+            self.sample_rate,
+            plan.output_channels as u16,
+            options.sample_format,
+        );
+        let mut quantizer =
+            wav::Quantizer::new(options.sample_format, options.dither, plan.output_channels);
+        let mut declicker = declicker();
+        let mut crossfeeder = crossfeeder();
+        let mut compressor = compressor();
+        let mut clipped = 0;
+        plan.for_each_window(window, |mut buffer| {
+            if let Some(declicker) = &mut declicker {
+                declicker.process(&mut buffer);
+            }
+            if let Some(crossfeeder) = &mut crossfeeder {
+                crossfeeder.process(&mut buffer);
+            }
+            if let Some(compressor) = &mut compressor {
+                compressor.process(&mut buffer);
+            }
+            if peak > 1.0 {
+                buffer.iter_mut().for_each(|s| *s /= peak);
+            }
+            clipped += dynamics::clip(&mut buffer, options.clip_mode);
+            wav::write_samples(&mut bytes, &buffer, &mut quantizer);
+        })?;
+        wav::pad_data(&mut bytes);
+        wav::write_markers(&mut bytes, &self.markers(options, 0, plan.frames));
+        Ok(CombinedAudioFile::wav(
+            bytes,
+            self.sample_rate,
+            plan.output_channels,
+            plan.frames,
+            clipped,
+        ))
+    }
 }
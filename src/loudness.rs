@@ -0,0 +1,86 @@
+use std::f64::consts::PI;
+
+use crate::filter::Biquad;
+
+/// The two K-weighting stages of ITU-R BS.1770, a high shelf modelling the
+/// head followed by a high-pass, designed for `sample_rate`.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+
+    let k = (PI * 1681.974450955533 / rate).tan();
+    let q = 0.7071752369554196;
+    let vh = 10f64.powf(3.999843853973347 / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        [
+            vh + vb * k / q + k * k,
+            2.0 * (k * k - vh),
+            vh - vb * k / q + k * k,
+        ],
+        [a0, 2.0 * (k * k - 1.0), 1.0 - k / q + k * k],
+    );
+
+    let k = (PI * 38.13547087602444 / rate).tan();
+    let q = 0.5003270373238773;
+    let high_pass = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [
+            1.0 + k / q + k * k,
+            2.0 * (k * k - 1.0),
+            1.0 - k / q + k * k,
+        ],
+    );
+
+    [shelf, high_pass]
+}
+
+/// Integrated loudness of interleaved `samples` in LUFS, following ITU-R
+/// BS.1770-4 with 400 ms blocks and the absolute and relative gates. Every
+/// channel is weighted equally, since the layout of expanded files is not
+/// known. Returns negative infinity for silence or audio shorter than one
+/// block.
+pub fn integrated_loudness(samples: &[f32], channels: usize, sample_rate: u32) -> f64 {
+    // Blocks overlap by 75%, so sum the weighted energy per 100 ms step and
+    // add up four steps per block
+    let step = (sample_rate / 10).max(1) as usize;
+    let frames = samples.len() / channels;
+    let mut step_energy = vec![0.0f64; frames / step];
+    for channel in 0..channels {
+        let mut filters = k_weighting(sample_rate);
+        for (frame, sample) in samples
+            .iter()
+            .skip(channel)
+            .step_by(channels)
+            .take(step_energy.len() * step)
+            .enumerate()
+        {
+            let filtered = filters
+                .iter_mut()
+                .fold(*sample as f64, |x, filter| filter.process(x));
+            step_energy[frame / step] += filtered * filtered;
+        }
+    }
+
+    let block_energy: Vec<f64> = step_energy
+        .windows(4)
+        .map(|steps| steps.iter().sum::<f64>() / (4 * step) as f64)
+        .collect();
+    let loudness = |energy: f64| -0.691 + 10.0 * energy.log10();
+    let gated_mean = |threshold: f64| {
+        let gated: Vec<f64> = block_energy
+            .iter()
+            .copied()
+            .filter(|&energy| loudness(energy) > threshold)
+            .collect();
+        gated.iter().sum::<f64>() / gated.len() as f64
+    };
+
+    let relative_gate = loudness(gated_mean(-70.0)) - 10.0;
+    let integrated = loudness(gated_mean(relative_gate.max(-70.0)));
+    if integrated.is_nan() {
+        f64::NEG_INFINITY
+    } else {
+        integrated
+    }
+}
@@ -0,0 +1,164 @@
+use std::ops::AddAssign;
+
+use crate::cancel::Cancel;
+use crate::envelope::{Automation, Fade};
+use crate::AudioError;
+
+/// One file's contribution to the mix timeline.
+pub struct Track<'a> {
+    pub samples: &'a [f32],
+    /// Timeline frame where the first sample lands.
+    pub offset: usize,
+    /// Gain for each channel of a frame.
+    pub gains: Vec<f32>,
+    pub fade: Fade,
+    /// Repeat the samples until the end of the timeline.
+    pub looping: bool,
+    /// Scale of the side signal of stereo samples, `1.0` leaves them as is.
+    pub width: f32,
+    /// Gain over the timeline, on top of `gains` and `fade`.
+    pub automation: Automation,
+}
+
+/// Everything needed to render any window of a mix.
+pub struct MixPlan<'a> {
+    pub tracks: Vec<Track<'a>>,
+    /// Channels of the tracks' samples.
+    pub channels: usize,
+    /// Channels of the rendered output.
+    pub output_channels: usize,
+    /// Length of the timeline in frames.
+    pub frames: usize,
+    /// Checked before each track and window is rendered.
+    pub cancel: Cancel<'a>,
+    /// Sum the tracks in `f64`, rounding to `f32` only once per sample.
+    pub high_precision: bool,
+    /// Gain applied to the summed tracks.
+    pub master_gain: f32,
+}
+
+impl MixPlan<'_> {
+    /// Mixes `frames` timeline frames starting at frame `start` into
+    /// interleaved samples with `output_channels` channels.
+    pub fn render(&self, start: usize, frames: usize) -> Result<Vec<f32>, AudioError> {
+        let mut buffer = Vec::new();
+        self.render_into(start, frames, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Like `render`, but reuses the allocation of `buffer`.
+    pub fn render_into(
+        &self,
+        start: usize,
+        frames: usize,
+        buffer: &mut Vec<f32>,
+    ) -> Result<(), AudioError> {
+        let channels = self.channels;
+        buffer.clear();
+        if self.high_precision {
+            let mut wide = vec![0.0f64; frames * channels];
+            self.sum_tracks(start, frames, &mut wide)?;
+            buffer.extend(wide.iter().map(|&s| s as f32));
+        } else {
+            buffer.resize(frames * channels, 0.0);
+            self.sum_tracks(start, frames, buffer)?;
+        }
+        if self.master_gain != 1.0 {
+            for sample in buffer.iter_mut() {
+                *sample *= self.master_gain;
+            }
+        }
+
+        // Collapse to mono by averaging every channel of a frame, in place
+        if self.output_channels == 1 && channels > 1 {
+            for frame in 0..frames {
+                let start = frame * channels;
+                buffer[frame] =
+                    buffer[start..start + channels].iter().sum::<f32>() / channels as f32;
+            }
+            buffer.truncate(frames);
+        }
+        Ok(())
+    }
+
+    /// Adds every track's contribution to timeline frames `start..start +
+    /// frames` into the zeroed, interleaved `buffer`.
+    fn sum_tracks<T: AddAssign + From<f32>>(
+        &self,
+        start: usize,
+        frames: usize,
+        buffer: &mut [T],
+    ) -> Result<(), AudioError> {
+        let channels = self.channels;
+        let end = start + frames;
+
+        for track in &self.tracks {
+            self.cancel.check()?;
+            let track_frames = track.samples.len() / channels;
+            if track_frames == 0 {
+                continue;
+            }
+            let from = track.offset.max(start);
+            let to = if track.looping {
+                end
+            } else {
+                (track.offset + track_frames).min(end)
+            };
+
+            // Mix in runs that never cross the end of the source, so a looping
+            // track wraps back to its first frame without a gap
+            let mut frame = from;
+            while frame < to {
+                let position = frame - track.offset;
+                let source_start = position % track_frames;
+                let run = (track_frames - source_start).min(to - frame);
+
+                let source =
+                    &track.samples[source_start * channels..(source_start + run) * channels];
+                let region =
+                    &mut buffer[(frame - start) * channels..(frame - start + run) * channels];
+
+                for (i, (m_frame, f_frame)) in region
+                    .chunks_exact_mut(channels)
+                    .zip(source.chunks_exact(channels))
+                    .enumerate()
+                {
+                    let envelope_gain =
+                        track.fade.gain_at(position + i) * track.automation.gain_at(frame + i);
+                    if track.width != 1.0 {
+                        let mid = (f_frame[0] + f_frame[1]) * 0.5;
+                        let side = (f_frame[0] - f_frame[1]) * 0.5 * track.width;
+                        m_frame[0] += ((mid + side) * track.gains[0] * envelope_gain).into();
+                        m_frame[1] += ((mid - side) * track.gains[1] * envelope_gain).into();
+                        continue;
+                    }
+                    for ((m_sample, &f_sample), &gain) in
+                        m_frame.iter_mut().zip(f_frame).zip(&track.gains)
+                    {
+                        *m_sample += (f_sample * gain * envelope_gain).into();
+                    }
+                }
+
+                frame += run;
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders the whole timeline `window` frames at a time, passing each
+    /// rendered window to `f`.
+    pub fn for_each_window(
+        &self,
+        window: usize,
+        mut f: impl FnMut(Vec<f32>),
+    ) -> Result<(), AudioError> {
+        let window = window.max(1);
+        let mut start = 0;
+        while start < self.frames {
+            let frames = window.min(self.frames - start);
+            f(self.render(start, frames)?);
+            start += frames;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+/// Linearly resamples interleaved `samples` from `from_rate` to `to_rate`.
+pub fn resample_linear(samples: &[f32], channels: usize, from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate || samples.is_empty() {
+        return samples.to_vec();
+    }
+    // A zero rate has no meaningful step, treat it as the slowest possible
+    let from_rate = from_rate.max(1);
+
+    let in_frames = samples.len() / channels;
+    let out_frames = (in_frames as u64 * to_rate as u64).div_ceil(from_rate as u64) as usize;
+    let step = from_rate as f64 / to_rate as f64;
+
+    let mut out = Vec::with_capacity(out_frames * channels);
+    for frame in 0..out_frames {
+        let pos = frame as f64 * step;
+        let index = (pos as usize).min(in_frames - 1);
+        let next = (index + 1).min(in_frames - 1);
+        let frac = (pos - index as f64) as f32;
+
+        for channel in 0..channels {
+            let a = samples[index * channels + channel];
+            let b = samples[next * channels + channel];
+            out.push(a + (b - a) * frac);
+        }
+    }
+    out
+}
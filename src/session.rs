@@ -0,0 +1,70 @@
+use wasm_bindgen::prelude::*;
+
+use crate::{wav, AudioCombiner, AudioError, CombinedAudioFile, MixOptions};
+
+/// A combiner held together with its mix settings, for interactive use where
+/// only the volumes change between mixes. The float mix buffer is kept and
+/// reused across updates instead of being reallocated for each one.
+#[wasm_bindgen]
+pub struct MixSession {
+    combiner: AudioCombiner,
+    options: MixOptions,
+    master_buffer: Vec<f32>,
+}
+
+#[wasm_bindgen]
+impl MixSession {
+    /// Re-mixes with new per-file `volumes`, keeping every other setting.
+    pub fn update_volumes(&mut self, volumes: Vec<f32>) -> Result<CombinedAudioFile, AudioError> {
+        self.options.volumes = volumes;
+        self.render()
+    }
+
+    /// Replaces all mix settings and re-mixes.
+    pub fn update_options(&mut self, options: MixOptions) -> Result<CombinedAudioFile, AudioError> {
+        self.options = options;
+        self.render()
+    }
+
+    /// Ends the session and returns the combiner.
+    pub fn finish(self) -> AudioCombiner {
+        self.combiner
+    }
+}
+
+impl MixSession {
+    pub(crate) fn new(combiner: AudioCombiner, options: MixOptions) -> Self {
+        Self {
+            combiner,
+            options,
+            master_buffer: Vec::new(),
+        }
+    }
+
+    fn render(&mut self) -> Result<CombinedAudioFile, AudioError> {
+        let plan = self.combiner.plan(&self.options)?;
+        let wav_size = self.combiner.wav_size(&self.options, &plan, plan.frames);
+        self.combiner
+            .check_budget(&self.options, &plan, plan.frames, wav_size)?;
+        let clipped = self
+            .combiner
+            .mix_into(&self.options, &plan, &mut self.master_buffer)?;
+        let channels = plan.output_channels;
+        let frames = plan.frames;
+        let mut bytes = wav::create_wav_container(
+            &self.master_buffer,
+            self.combiner.sample_rate,
+            channels as u16,
+            self.options.sample_format,
+            self.options.dither,
+        );
+        wav::write_markers(&mut bytes, &self.combiner.markers(&self.options, 0, frames));
+        Ok(CombinedAudioFile::wav(
+            bytes,
+            self.combiner.sample_rate,
+            channels,
+            frames,
+            clipped,
+        ))
+    }
+}
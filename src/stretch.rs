@@ -0,0 +1,78 @@
+use std::f64::consts::PI;
+
+/// Length of each overlap-added frame in seconds.
+const FRAME_SECONDS: f64 = 0.04;
+
+/// Changes the tempo of interleaved `samples` by `factor` without changing
+/// their pitch, using WSOLA (waveform-similarity overlap-add). A factor of
+/// `2.0` plays twice as fast and halves the length.
+///
+/// Each output frame is taken from near its nominal source position, at the
+/// offset whose waveform best continues the previous frame. Similarity is
+/// measured on the sum of all channels and every channel is cut at the same
+/// offset, so the stereo image is preserved.
+pub fn wsola(samples: &[f32], channels: usize, sample_rate: u32, factor: f64) -> Vec<f32> {
+    let in_frames = samples.len() / channels;
+    let out_frames = (in_frames as f64 / factor).round() as usize;
+    let frame_len = ((sample_rate as f64 * FRAME_SECONDS) as usize / 2 * 2).max(4);
+    let synthesis_hop = frame_len / 2;
+    let analysis_hop = synthesis_hop as f64 * factor;
+    let tolerance = frame_len / 8;
+
+    let window: Vec<f32> = (0..frame_len)
+        .map(|i| (0.5 - 0.5 * (2.0 * PI * i as f64 / frame_len as f64).cos()) as f32)
+        .collect();
+    let mono: Vec<f32> = samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum())
+        .collect();
+    let mono_at = |frame: usize| mono.get(frame).copied().unwrap_or(0.0);
+
+    let mut out = vec![0.0f32; (out_frames + frame_len) * channels];
+    let mut norm = vec![0.0f32; out_frames + frame_len];
+    let mut previous = 0;
+    let mut k = 0;
+    while k * synthesis_hop < out_frames {
+        let out_pos = k * synthesis_hop;
+        // Keep whole frames inside the source so the end doesn't fade to silence
+        let last = in_frames.saturating_sub(frame_len);
+        let nominal = ((k as f64 * analysis_hop) as usize).min(last);
+        let position = if k == 0 {
+            0
+        } else {
+            // Pick the offset that best matches where the previous frame
+            // would naturally continue
+            let target = previous + synthesis_hop;
+            let from = nominal.saturating_sub(tolerance);
+            let to = (nominal + tolerance).min(last);
+            (from..=to)
+                .map(|p| {
+                    let score: f32 = (0..synthesis_hop)
+                        .map(|i| mono_at(target + i) * mono_at(p + i))
+                        .sum();
+                    (p, score)
+                })
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .map_or(nominal, |(p, _)| p)
+        };
+
+        for (i, &w) in window.iter().enumerate() {
+            let source = (position + i) * channels;
+            let dest = (out_pos + i) * channels;
+            for c in 0..channels {
+                out[dest + c] += samples.get(source + c).copied().unwrap_or(0.0) * w;
+            }
+            norm[out_pos + i] += w;
+        }
+        previous = position;
+        k += 1;
+    }
+
+    out.truncate(out_frames * channels);
+    for (frame, &weight) in out.chunks_exact_mut(channels).zip(&norm) {
+        if weight > 1e-6 {
+            frame.iter_mut().for_each(|s| *s /= weight);
+        }
+    }
+    out
+}
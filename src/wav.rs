@@ -0,0 +1,326 @@
+use std::convert::TryInto;
+
+use crate::{AudioError, Dither, SampleFormat, WavHeader};
+
+pub fn create_wav_container(
+    samples: &[f32],
+    sample_rate: u32,
+    channels: u16,
+    format: SampleFormat,
+    dither: Dither,
+) -> Vec<u8> {
+    let mut wav = Vec::with_capacity(file_size(samples.len(), channels, format));
+    write_header(&mut wav, samples.len(), sample_rate, channels, format);
+    write_samples(
+        &mut wav,
+        samples,
+        &mut Quantizer::new(format, dither, channels as usize),
+    );
+    pad_data(&mut wav);
+    wav
+}
+
+/// Size of the RIFF, `fmt ` and `data` headers written by `write_header`.
+pub fn header_size(channels: u16) -> usize {
+    20 + fmt_size(channels) as usize + 8
+}
+
+/// Size of the `fmt ` chunk body: the classic 16 bytes for mono and stereo,
+/// 40 for `WAVE_FORMAT_EXTENSIBLE`, which players expect for more channels.
+fn fmt_size(channels: u16) -> u32 {
+    if channels > 2 {
+        40
+    } else {
+        16
+    }
+}
+
+/// Speaker positions of the channels in order, for the extensible `fmt `
+/// chunk: the usual layouts up to 7.1, unassigned beyond.
+fn channel_mask(channels: u16) -> u32 {
+    match channels {
+        1 => 0x4,   // FC
+        2 => 0x3,   // FL FR
+        3 => 0x7,   // FL FR FC
+        4 => 0x33,  // FL FR BL BR
+        5 => 0x37,  // FL FR FC BL BR
+        6 => 0x3f,  // FL FR FC LFE BL BR
+        7 => 0x13f, // FL FR FC LFE BL BR BC
+        8 => 0x63f, // FL FR FC LFE BL BR SL SR
+        _ => 0,
+    }
+}
+
+/// Exact size in bytes of a WAV file holding `sample_count` samples, so the
+/// output can be allocated once up front.
+pub fn file_size(sample_count: usize, channels: u16, format: SampleFormat) -> usize {
+    file_size_u64(sample_count as u64, channels, format) as usize
+}
+
+/// Like `file_size`, but in `u64` so sizes beyond a 32-bit `usize` can be
+/// checked before anything is allocated.
+pub fn file_size_u64(sample_count: u64, channels: u16, format: SampleFormat) -> u64 {
+    let data_size = sample_count * bytes_per_sample(format) as u64;
+    header_size(channels) as u64 + data_size + data_size % 2
+}
+
+pub fn bytes_per_sample(format: SampleFormat) -> u16 {
+    match format {
+        SampleFormat::Pcm8 => 1,
+        SampleFormat::Pcm16 => 2,
+        SampleFormat::Pcm24 => 3,
+        SampleFormat::Float32 => 4,
+    }
+}
+
+/// Converts float samples to the output format, keeping dither state between
+/// calls so a mix can be written in several pieces.
+pub struct Quantizer {
+    format: SampleFormat,
+    dither: Dither,
+    rng_state: u32,
+    /// Last quantization error of each channel, for noise shaping.
+    errors: Vec<f32>,
+    channel: usize,
+}
+
+impl Quantizer {
+    pub fn new(format: SampleFormat, dither: Dither, channels: usize) -> Self {
+        Self {
+            format,
+            dither,
+            rng_state: 0x9E37_79B9,
+            errors: vec![0.0; channels.max(1)],
+            channel: 0,
+        }
+    }
+
+    /// Uniform random value in `[0, 1)` from a xorshift generator. Seeded
+    /// the same every time so output stays reproducible.
+    fn next_uniform(&mut self) -> f32 {
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 17;
+        self.rng_state ^= self.rng_state << 5;
+        (self.rng_state >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    /// Triangular noise spanning +-1 LSB.
+    fn tpdf(&mut self) -> f32 {
+        self.next_uniform() - self.next_uniform()
+    }
+
+    /// Quantizes `sample` to an integer in `[-max, max]`.
+    fn quantize(&mut self, sample: f32, max: i32) -> i32 {
+        let max = max as f32;
+        let scaled = sample.clamp(-1.0, 1.0) * max;
+        let channel = self.channel;
+        self.channel = (self.channel + 1) % self.errors.len();
+
+        match self.dither {
+            Dither::None => scaled as i32,
+            Dither::Tpdf => (scaled + self.tpdf()).round().clamp(-max - 1.0, max) as i32,
+            // First-order error feedback pushes the dither noise up towards
+            // high frequencies, where it is less audible
+            Dither::NoiseShaped => {
+                let wanted = scaled - self.errors[channel];
+                let quantized = (wanted + self.tpdf()).round().clamp(-max - 1.0, max);
+                self.errors[channel] = quantized - wanted;
+                quantized as i32
+            }
+        }
+    }
+
+    /// Appends the encoding of `sample` to `wav`.
+    pub fn push(&mut self, wav: &mut Vec<u8>, sample: f32) {
+        match self.format {
+            // 8-bit WAV samples are unsigned
+            SampleFormat::Pcm8 => {
+                let s = self.quantize(sample, i8::MAX as i32) + 128;
+                wav.push(s as u8);
+            }
+            SampleFormat::Pcm16 => {
+                let s = self.quantize(sample, i16::MAX as i32) as i16;
+                wav.extend_from_slice(&s.to_le_bytes());
+            }
+            SampleFormat::Pcm24 => {
+                let s = self.quantize(sample, (1 << 23) - 1);
+                wav.extend_from_slice(&s.to_le_bytes()[..3]);
+            }
+            // Float output keeps the mix as is, including any overs
+            SampleFormat::Float32 => wav.extend_from_slice(&sample.to_le_bytes()),
+        }
+    }
+}
+
+/// Writes the RIFF, `fmt ` and `data` headers for `sample_count` interleaved
+/// samples that will follow. More than two channels get an extensible
+/// `fmt ` chunk.
+pub fn write_header(
+    wav: &mut Vec<u8>,
+    sample_count: usize,
+    sample_rate: u32,
+    channels: u16,
+    format: SampleFormat,
+) {
+    let bytes_per_sample = bytes_per_sample(format);
+    let data_size = sample_count as u32 * bytes_per_sample as u32;
+    let format_tag: u16 = match format {
+        SampleFormat::Float32 => 3, // WAVE_FORMAT_IEEE_FLOAT
+        _ => 1,                     // WAVE_FORMAT_PCM
+    };
+    let fmt_size = fmt_size(channels);
+    let extensible = fmt_size > 16;
+
+    // RIFF Header
+    wav.extend_from_slice(b"RIFF");
+    // The RIFF size counts the pad byte of an odd-sized data chunk
+    wav.extend_from_slice(&(20 + fmt_size + data_size + data_size % 2).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+
+    // fmt chunk
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&fmt_size.to_le_bytes());
+    if extensible {
+        wav.extend_from_slice(&0xfffeu16.to_le_bytes()); // WAVE_FORMAT_EXTENSIBLE
+    } else {
+        wav.extend_from_slice(&format_tag.to_le_bytes());
+    }
+    wav.extend_from_slice(&channels.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * channels as u32 * bytes_per_sample as u32).to_le_bytes());
+    wav.extend_from_slice(&(channels * bytes_per_sample).to_le_bytes());
+    wav.extend_from_slice(&(bytes_per_sample * 8).to_le_bytes());
+    if extensible {
+        wav.extend_from_slice(&22u16.to_le_bytes()); // extension size
+        wav.extend_from_slice(&(bytes_per_sample * 8).to_le_bytes()); // valid bits
+        wav.extend_from_slice(&channel_mask(channels).to_le_bytes());
+        // The subformat GUID is the format tag followed by a fixed suffix
+        wav.extend_from_slice(&(format_tag as u32).to_le_bytes());
+        wav.extend_from_slice(&[
+            0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+        ]);
+    }
+
+    // data chunk
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_size.to_le_bytes());
+}
+
+/// Appends `samples` to the `data` chunk in the quantizer's format.
+pub fn write_samples(wav: &mut Vec<u8>, samples: &[f32], quantizer: &mut Quantizer) {
+    for &sample in samples {
+        quantizer.push(wav, sample);
+    }
+}
+
+/// Ends the `data` chunk once every sample is written. Chunks start at even
+/// offsets, so an odd-sized one, e.g. 8-bit mono with an odd frame count,
+/// gets a pad byte. The headers are always an even size, so an odd length
+/// means an odd data chunk.
+pub fn pad_data(wav: &mut Vec<u8>) {
+    if wav.len() % 2 == 1 {
+        wav.push(0);
+    }
+}
+
+/// Appends a `cue ` chunk and a `LIST`/`adtl` chunk labeling each cue
+/// point, after the padded `data` chunk, and updates the RIFF size.
+/// `markers` are `(frame, label)` pairs. Nothing is written without markers.
+pub fn write_markers(wav: &mut Vec<u8>, markers: &[(u32, &str)]) {
+    if markers.is_empty() {
+        return;
+    }
+
+    wav.extend_from_slice(b"cue ");
+    wav.extend_from_slice(&(4 + 24 * markers.len() as u32).to_le_bytes());
+    wav.extend_from_slice(&(markers.len() as u32).to_le_bytes());
+    for (id, &(frame, _)) in (1u32..).zip(markers) {
+        wav.extend_from_slice(&id.to_le_bytes());
+        wav.extend_from_slice(&frame.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&0u32.to_le_bytes()); // chunk start
+        wav.extend_from_slice(&0u32.to_le_bytes()); // block start
+        wav.extend_from_slice(&frame.to_le_bytes());
+    }
+
+    let list_start = wav.len();
+    wav.extend_from_slice(b"LIST");
+    wav.extend_from_slice(&0u32.to_le_bytes());
+    wav.extend_from_slice(b"adtl");
+    for (id, &(_, label)) in (1u32..).zip(markers) {
+        // The label is NUL-terminated, and the chunk padded to an even size
+        let size = 4 + label.len() as u32 + 1;
+        wav.extend_from_slice(b"labl");
+        wav.extend_from_slice(&size.to_le_bytes());
+        wav.extend_from_slice(&id.to_le_bytes());
+        wav.extend_from_slice(label.as_bytes());
+        wav.push(0);
+        if size % 2 == 1 {
+            wav.push(0);
+        }
+    }
+    let list_size = (wav.len() - list_start - 8) as u32;
+    wav[list_start + 4..list_start + 8].copy_from_slice(&list_size.to_le_bytes());
+
+    let riff_size = (wav.len() - 8) as u32;
+    wav[4..8].copy_from_slice(&riff_size.to_le_bytes());
+}
+
+/// Reads the `fmt ` fields and `data` size back from a RIFF WAVE file,
+/// skipping any other chunks.
+pub fn parse_header(wav: &[u8]) -> Result<WavHeader, AudioError> {
+    let invalid = |what: &str| AudioError::InvalidInput(format!("Not a valid WAV file: {}", what));
+    let u16_at = |bytes: &[u8], at: usize| {
+        bytes
+            .get(at..at.checked_add(2)?)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+    };
+    let u32_at = |bytes: &[u8], at: usize| {
+        bytes
+            .get(at..at.checked_add(4)?)
+            .and_then(|b| b.try_into().ok())
+            .map(u32::from_le_bytes)
+    };
+    let chunk = |pos: usize| {
+        let id = wav.get(pos..pos.checked_add(4)?)?;
+        Some((id, u32_at(wav, pos + 4)?))
+    };
+
+    if wav.get(0..4) != Some(b"RIFF") || wav.get(8..12) != Some(b"WAVE") {
+        return Err(invalid("missing RIFF/WAVE signature"));
+    }
+
+    let mut fmt = None;
+    let mut data_size = None;
+    let mut pos = 12;
+    while let Some((id, size)) = chunk(pos) {
+        let body = pos + 8;
+        match id {
+            b"fmt " => fmt = wav.get(body..body + 16),
+            b"data" => data_size = Some(size),
+            _ => {}
+        }
+        // Chunks are padded to an even size. Streaming writers may leave the
+        // size at 0xFFFFFFFF, which can run past the end of a 32-bit usize.
+        pos = match (size as usize)
+            .checked_add(size as usize % 2)
+            .and_then(|len| body.checked_add(len))
+        {
+            Some(next) => next,
+            None => break,
+        };
+    }
+
+    let fmt = fmt.ok_or_else(|| invalid("missing fmt chunk"))?;
+    let data_size = data_size.ok_or_else(|| invalid("missing data chunk"))?;
+    let field = |at| u16_at(fmt, at).unwrap_or_default();
+    Ok(WavHeader {
+        format_tag: field(0),
+        channels: field(2),
+        sample_rate: u32_at(fmt, 4).unwrap_or_default(),
+        byte_rate: u32_at(fmt, 8).unwrap_or_default(),
+        block_align: field(12),
+        bits_per_sample: field(14),
+        data_size,
+    })
+}
@@ -5,9 +5,212 @@
 extern crate wasm_bindgen_test;
 use wasm_bindgen_test::*;
 
+use wasm_audio_combiner::{
+    parse_wav_header, AudioCombiner, MixOptions, SingleAudioFile, SingleAudioFileType,
+};
+
 wasm_bindgen_test_configure!(run_in_browser);
 
+/// A 16-bit PCM WAV file holding the interleaved `samples`.
+fn wav(samples: &[i16], sample_rate: u32, channels: u16) -> Vec<u8> {
+    let data_size = samples.len() as u32 * 2;
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_size).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&channels.to_le_bytes());
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(sample_rate * channels as u32 * 2).to_le_bytes());
+    bytes.extend_from_slice(&(channels * 2).to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+    bytes
+}
+
+fn combiner(samples: &[i16], sample_rate: u32, channels: u16) -> AudioCombiner {
+    let file = SingleAudioFile::new(
+        wav(samples, sample_rate, channels),
+        SingleAudioFileType::Wav,
+    );
+    AudioCombiner::new(vec![file], None, None, None, None).unwrap()
+}
+
 #[wasm_bindgen_test]
 fn pass() {
     assert_eq!(1 + 1, 2);
 }
+
+#[wasm_bindgen_test]
+fn reverse_keeps_channels_paired_and_round_trips() {
+    let mut combiner = combiner(&[100, -100, 200, -200, 300, -300], 8000, 2);
+    let original = combiner.combine_raw(vec![1.0]).unwrap();
+
+    combiner.reverse(0).unwrap();
+    let reversed = combiner.combine_raw(vec![1.0]).unwrap();
+    let frames: Vec<&[f32]> = original.chunks(2).rev().collect();
+    assert_eq!(reversed, frames.concat());
+
+    combiner.reverse(0).unwrap();
+    assert_eq!(combiner.combine_raw(vec![1.0]).unwrap(), original);
+}
+
+#[wasm_bindgen_test]
+fn swapping_channels_twice_restores_the_original() {
+    let mut combiner = combiner(&[100, -100, 200, -200, 300, -300], 8000, 2);
+    let original = combiner.combine_raw(vec![1.0]).unwrap();
+
+    combiner.swap_channels(0).unwrap();
+    let swapped = combiner.combine_raw(vec![1.0]).unwrap();
+    let frames: Vec<[f32; 2]> = original.chunks(2).map(|f| [f[1], f[0]]).collect();
+    assert_eq!(swapped, frames.concat());
+
+    combiner.swap_channels(0).unwrap();
+    assert_eq!(combiner.combine_raw(vec![1.0]).unwrap(), original);
+}
+
+#[wasm_bindgen_test]
+fn partial_trailing_frame_is_dropped() {
+    // Five samples in a stereo file leave half a frame at the end
+    let combiner = combiner(&[1000, -1000, 2000, -2000, 3000], 8000, 2);
+    assert_eq!(combiner.file_info(0).unwrap().sample_count, 2);
+
+    let mix = combiner.combine_raw(vec![1.0]).unwrap();
+    assert_eq!(mix.len(), 4);
+    assert!(mix[1] < 0.0 && mix[3] < 0.0);
+}
+
+#[wasm_bindgen_test]
+fn mix_does_not_depend_on_file_order() {
+    let sources = [
+        (wav(&[1000, -2000, 3000, -4000, 5000, -6000], 8000, 2), 0.5),
+        (wav(&[-700, 1400, 2100, 2800], 11025, 1), 1.25),
+        (
+            wav(&[9000, 8000, 7000, 6000, 5000, 4000, 3000, 2000], 8000, 2),
+            0.8,
+        ),
+    ];
+    let orders = [
+        [0, 1, 2],
+        [0, 2, 1],
+        [1, 0, 2],
+        [1, 2, 0],
+        [2, 0, 1],
+        [2, 1, 0],
+    ];
+
+    let mix = |order: &[usize; 3]| {
+        let files = order
+            .iter()
+            .map(|&i| SingleAudioFile::new(sources[i].0.clone(), SingleAudioFileType::Wav))
+            .collect();
+        let volumes = order.iter().map(|&i| sources[i].1).collect();
+        AudioCombiner::new(files, None, None, None, None)
+            .unwrap()
+            .combine_raw(volumes)
+            .unwrap()
+    };
+
+    let expected = mix(&orders[0]);
+    for order in &orders[1..] {
+        let actual = mix(order);
+        assert_eq!(actual.len(), expected.len(), "order {:?}", order);
+        for (a, e) in actual.iter().zip(&expected) {
+            assert!((a - e).abs() < 1e-6, "order {:?}: {} vs {}", order, a, e);
+        }
+    }
+}
+
+#[wasm_bindgen_test]
+fn inverted_copy_cancels_the_original() {
+    let bytes = wav(&[1200, -3400, 5600, -7800, 9000, 100], 8000, 2);
+    let files = vec![
+        SingleAudioFile::new(bytes.clone(), SingleAudioFileType::Wav),
+        SingleAudioFile::new(bytes, SingleAudioFileType::Wav),
+    ];
+    let combiner = AudioCombiner::new(files, None, None, None, None).unwrap();
+
+    let mut options = MixOptions::new(vec![1.0, 1.0]);
+    options.set_inverted(1, true);
+    let mix = combiner.combine_raw_with(&options).unwrap();
+    assert_eq!(mix.len(), 6);
+    assert!(mix.iter().all(|&sample| sample == 0.0), "{:?}", mix);
+}
+
+#[wasm_bindgen_test]
+fn non_finite_samples_are_sanitized() {
+    // A 32-bit float WAV with a NaN and both infinities among valid samples
+    let samples = [
+        0.25f32,
+        f32::NAN,
+        f32::INFINITY,
+        f32::NEG_INFINITY,
+        -0.5,
+        0.125,
+    ];
+    let data_size = samples.len() as u32 * 4;
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_size).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&3u16.to_le_bytes());
+    bytes.extend_from_slice(&2u16.to_le_bytes());
+    bytes.extend_from_slice(&8000u32.to_le_bytes());
+    bytes.extend_from_slice(&(8000u32 * 8).to_le_bytes());
+    bytes.extend_from_slice(&8u16.to_le_bytes());
+    bytes.extend_from_slice(&32u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_size.to_le_bytes());
+    for sample in &samples {
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+
+    let file = SingleAudioFile::new(bytes, SingleAudioFileType::Wav);
+    let combiner = AudioCombiner::new(vec![file], None, None, None, None).unwrap();
+    let mix = combiner.combine_raw(vec![1.0]).unwrap();
+    assert!(mix.iter().all(|s| s.is_finite()), "{:?}", mix);
+    assert_eq!(mix[1], 0.0);
+    assert!(mix[2] > 0.99 && mix[3] < -0.99, "{:?}", mix);
+    assert_eq!(combiner.file_levels()[0].peak, 1.0);
+}
+
+#[wasm_bindgen_test]
+fn wav_header_matches_the_mix() {
+    let combiner = combiner(&[100, -100, 200, -200, 300, -300], 8000, 2);
+    let mix = combiner.combine(vec![1.0]).unwrap();
+    let header = parse_wav_header(&mix.bytes).unwrap();
+    assert_eq!(header.format_tag, 1);
+    assert_eq!(header.channels, 2);
+    assert_eq!(header.sample_rate, 8000);
+    assert_eq!(header.byte_rate, 8000 * 4);
+    assert_eq!(header.block_align, 4);
+    assert_eq!(header.bits_per_sample, 16);
+    assert_eq!(header.data_size as usize, mix.bytes.len() - 44);
+}
+
+#[wasm_bindgen_test]
+fn constant_signal_stays_constant_through_long_crossfades() {
+    // The fade is longer than half of the middle file
+    let file =
+        |frames| SingleAudioFile::new(wav(&vec![16384; frames], 1000, 2), SingleAudioFileType::Wav);
+    let combiner = AudioCombiner::new(
+        vec![file(200), file(100), file(200)],
+        None,
+        None,
+        None,
+        None,
+    )
+    .unwrap();
+    let mix = combiner.concat_crossfade(80.0).unwrap();
+    assert_eq!(mix.bytes.len(), 44 + 200 * 4);
+    for sample in mix.bytes[44..].chunks(2) {
+        let sample = i16::from_le_bytes([sample[0], sample[1]]);
+        assert!((sample - 16383).abs() <= 1, "{}", sample);
+    }
+}